@@ -1,100 +1,275 @@
 use bevy::prelude::*;
-use bevy_ggrs::{AddRollbackCommandExtension, PlayerInputs};
-use rand_xoshiro::rand_core::SeedableRng;
-use rand_xoshiro::Xoshiro256PlusPlus;
-use rand::{Rng};
+use bevy_ggrs::{AddRollbackCommandExtension, PlayerInputs, RollbackFrameCount};
 use crate::{WORLD_SIZE, GameTextures};
-use crate::network_manager::{RandomSeed};
 use crate::GameConfig;
-use crate::player_module::{Player, PLAYER_RADIUS};
+use crate::player_module::{Player, RoundOutcome, Slowed};
 use crate::projectile::Projectile;
 use crate::input_handler::*;
-use crate::utilities::PlayerScores;
+use crate::events::{BarrierPlaced, PendingEvents};
+use crate::maps::{cell_center, ChosenMap};
+use crate::utilities::{FrameTimer, GameRng};
+use crate::settings::GameSettings;
+
+/// Thickness of the walls around the map. Anything moving less than half
+/// of this in one frame can't pass through them.
+const BOUNDARY_WALL_THICKNESS: f32 = 10.0;
+const BOUNDARY_WALL_COLOR: Color = Color::srgb(0.2, 0.2, 0.2);
+
+/// Opacity of slow tiles, which are drawn under players since they don't block them
+const SLOW_TILE_ALPHA: f32 = 0.35;
 
 #[derive(Component, Clone, Copy)]
 pub struct Barrier {
-    pub(crate) player_placed: bool,
+    /// Handle of the player who placed the barrier, `None` for the map's own walls
+    pub(crate) placed_by: Option<usize>,
+    pub(crate) kind: BarrierKind,
+    /// Shots the barrier has absorbed so far, see `Barrier::hits_to_break`
+    pub(crate) hits_taken: u32,
+}
+
+/// Frames until a player-placed barrier crumbles, when barriers decay
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct BarrierDecay(FrameTimer);
+
+/// Cell the player's click was held on last frame, so a held click only
+/// places a barrier when it's pressed or dragged to another cell
+#[derive(Component, Clone, Copy, Default)]
+pub struct HeldClick(pub Option<(u8, u8)>);
+
+/// How the player who placed a barrier relates to someone shooting at it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BarrierRelation {
+    /// The shooter placed the barrier
+    Own,
+    /// The barrier was placed by one of the shooter's opponents
+    Enemy,
+    /// Part of the map, placed by nobody
+    Neutral,
+}
+
+/// What a barrier does to things that touch it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BarrierKind {
+    /// Blocks players, projectiles and sight
+    Solid,
+    /// Doesn't block anything, but slows down the placer's opponents standing on it
+    Slow {
+        /// Handle of the player who placed the tile, who isn't slowed by it
+        owner: usize,
+    },
 }
 
+impl Barrier {
+    /// Whether the barrier stops players, projectiles and sight
+    pub fn is_solid(&self) -> bool {
+        self.kind == BarrierKind::Solid
+    }
+
+    pub fn relation_to(&self, shooter: usize) -> BarrierRelation {
+        match self.placed_by {
+            Some(placer) if placer == shooter => BarrierRelation::Own,
+            Some(_) => BarrierRelation::Enemy,
+            None => BarrierRelation::Neutral,
+        }
+    }
+
+    /// Shots from `shooter` the barrier can take in total before it breaks
+    ///
+    /// The map's walls never break: they aren't rollback entities, so a
+    /// mispredicted shot couldn't bring them back.
+    pub fn hits_to_break(&self, shooter: usize, settings: &GameSettings) -> Option<u32> {
+        match self.relation_to(shooter) {
+            BarrierRelation::Own => Some(settings.own_barrier_hits),
+            BarrierRelation::Enemy => Some(settings.enemy_barrier_hits),
+            BarrierRelation::Neutral => None,
+        }
+    }
+
+    /// Opacity the barrier is drawn with before it starts to fade
+    pub fn alpha(&self) -> f32 {
+        match self.kind {
+            BarrierKind::Solid => 1.0,
+            BarrierKind::Slow { .. } => SLOW_TILE_ALPHA,
+        }
+    }
+}
+
+/// Places the barriers for a round, from the chosen preset map or generated from the match seed
 pub fn create_world(
     mut commands: Commands,
     barriers: Query<Entity, With<Barrier>>,
-    session_seed: Res<RandomSeed>,
     images: Res<GameTextures>,
-    playerscores: Res<PlayerScores>
+    mut rng: ResMut<GameRng>,
+    chosen_map: ChosenMap,
+    settings: Res<GameSettings>,
 ) {
     // Clear existing barriers
     for barrier in &barriers {
         commands.entity(barrier).despawn_recursive();
     }
 
-    let mut rng = Xoshiro256PlusPlus::seed_from_u64((1234u64 + playerscores.get(0) + playerscores.get(1)) ^ **session_seed);
+    // With wrapping edges, things leaving the map come back on the other side instead
+    if !settings.wrap_edges {
+        spawn_boundary_walls(&mut commands);
+    }
+
+    if let Some(map) = chosen_map.get() {
+        for cell in map.barrier_cells() {
+            spawn_barrier(&mut commands, cell, &images);
+        }
+        return;
+    }
 
     // Generate walls
     for _ in 0..20 {
         let max_box_size = WORLD_SIZE / 4;
-        let width = rng.gen_range(1..max_box_size);
-        let height = rng.gen_range(1..max_box_size);
+        let width = rng.range(1..max_box_size);
+        let height = rng.range(1..max_box_size);
 
-        let cell_x = rng.gen_range(0..=(WORLD_SIZE - width));
-        let cell_y = rng.gen_range(0..=(WORLD_SIZE - height));
+        let cell_x = rng.range(0..=(WORLD_SIZE - width));
+        let cell_y = rng.range(0..=(WORLD_SIZE - height));
 
         for dx in 0..width {
             for dy in 0..height {
-                // Calculate the position of each tile in the grid
-                let tile_x = cell_x + dx;
-                let tile_y = cell_y + dy;
-
-                // Convert the grid position to world position
-                let world_pos = Vec3::new(
-                    tile_x as f32 + 0.5 - WORLD_SIZE as f32 / 2.,
-                    tile_y as f32 + 0.5 - WORLD_SIZE as f32 / 2.,
-                    10.,
-                );
+                spawn_barrier(&mut commands, UVec2::new(cell_x + dx, cell_y + dy), &images);
+            }
+        }
+    }
+}
+
+/// Spawns a one cell barrier that's part of the map rather than placed by a player
+fn spawn_barrier(commands: &mut Commands, cell: UVec2, images: &GameTextures) {
+    commands.spawn((
+        Barrier {
+            placed_by: None,
+            kind: BarrierKind::Solid,
+            hits_taken: 0,
+        },
+        SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::ONE), // Each tile is 1x1
+                ..default()
+            },
+            texture: images.barrier_image.clone(),
+            transform: Transform::from_translation(cell_center(cell).extend(10.)),
+            ..default()
+        },
+    ));
+}
 
-                commands.spawn((
-                    Barrier {
-                        player_placed: false,
-                    },
-                    SpriteBundle {
-                        sprite: Sprite {
-                            custom_size: Some(Vec2::ONE), // Each tile is 1x1
-                            ..default()
-                        },
-                        texture: images.barrier_image.clone(),
-                        transform: Transform::from_translation(world_pos),
-                        ..default()
-                    },
-                ));
+/// Surrounds the map with walls, so the edges stop players and projectiles
+/// through the same collision checks as any other barrier
+fn spawn_boundary_walls(commands: &mut Commands) {
+    let half_world_size = WORLD_SIZE as f32 / 2.;
+    let center_offset = half_world_size + BOUNDARY_WALL_THICKNESS / 2.;
+    let length = WORLD_SIZE as f32 + BOUNDARY_WALL_THICKNESS * 2.;
+
+    let walls = [
+        (Vec2::new(0., center_offset), Vec2::new(length, BOUNDARY_WALL_THICKNESS)),
+        (Vec2::new(0., -center_offset), Vec2::new(length, BOUNDARY_WALL_THICKNESS)),
+        (Vec2::new(center_offset, 0.), Vec2::new(BOUNDARY_WALL_THICKNESS, length)),
+        (Vec2::new(-center_offset, 0.), Vec2::new(BOUNDARY_WALL_THICKNESS, length)),
+    ];
+
+    for (position, size) in walls {
+        commands.spawn((
+            Barrier {
+                placed_by: None,
+                kind: BarrierKind::Solid,
+                hits_taken: 0,
+            },
+            SpriteBundle {
+                sprite: Sprite {
+                    color: BOUNDARY_WALL_COLOR,
+                    custom_size: Some(size),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(10.)),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Finds where a ray first enters an axis aligned box, as a multiple of `direction`
+///
+/// Returns `None` if the ray misses the box or the box is behind the origin.
+/// An origin already inside the box gives zero.
+pub fn ray_box_entry(origin: Vec2, direction: Vec2, box_center: Vec2, box_half_size: Vec2) -> Option<f32> {
+    let mut t_min: f32 = 0.0;
+    let mut t_max = f32::INFINITY;
+
+    for axis in 0..2 {
+        let min = box_center[axis] - box_half_size[axis];
+        let max = box_center[axis] + box_half_size[axis];
+
+        if direction[axis].abs() < f32::EPSILON {
+            if origin[axis] < min || origin[axis] > max {
+                return None;
             }
+            continue;
+        }
+
+        let t1 = (min - origin[axis]) / direction[axis];
+        let t2 = (max - origin[axis]) / direction[axis];
+        t_min = t_min.max(t1.min(t2));
+        t_max = t_max.min(t1.max(t2));
+        if t_min > t_max {
+            return None;
         }
     }
+
+    Some(t_min)
+}
+
+/// Moves a player position out of a barrier along the axis of least overlap
+pub fn push_out_of_barrier(player_pos: Vec2, player_radius: f32, barrier_pos: Vec2, barrier_size: Vec2) -> Vec2 {
+    let barrier_to_player = player_pos - barrier_pos;
+
+    let barrier_corner_to_player = barrier_to_player.abs() - barrier_size / 2.;
+
+    let corner_to_corner = barrier_corner_to_player - Vec2::splat(player_radius);
+
+    if corner_to_corner.x > 0. || corner_to_corner.y > 0. {
+        return player_pos;
+    }
+
+    let mut resolved = player_pos;
+    if corner_to_corner.x > corner_to_corner.y {
+        resolved.x -= barrier_to_player.x.signum() * corner_to_corner.x;
+    } else {
+        resolved.y -= barrier_to_player.y.signum() * corner_to_corner.y;
+    }
+    resolved
 }
 
+/// Pushes players out of solid barriers and marks who is standing on an opponent's slow tile
 pub fn handle_barrier_collisions(
-    mut players: Query<&mut Transform, With<Player>>,
-    barriers: Query<(&Transform, &Sprite), (With<Barrier>, Without<Player>)>,
+    mut players: Query<(&mut Transform, &Player, &mut Slowed)>,
+    barriers: Query<(&Barrier, &Transform, &Sprite), Without<Player>>,
+    settings: Res<GameSettings>,
 ) {
-    for mut player_transform in &mut players {
-        for (barrier_transform, barrier_sprite) in &barriers {
+    for (mut player_transform, player, mut slowed) in &mut players {
+        slowed.0 = false;
+
+        for (barrier, barrier_transform, barrier_sprite) in &barriers {
             let barrier_size = barrier_sprite.custom_size.expect("Barrier has no size");
             let barrier_pos = barrier_transform.translation.xy();
             let player_pos = player_transform.translation.xy();
 
-            let barrier_to_player = player_pos - barrier_pos;
-
-            let barrier_corner_to_player = barrier_to_player.abs() - barrier_size / 2.;
+            let resolved = push_out_of_barrier(player_pos, settings.player_radius, barrier_pos, barrier_size);
 
-            let corner_to_corner = barrier_corner_to_player - Vec2::splat(PLAYER_RADIUS);
-
-            if corner_to_corner.x > 0. || corner_to_corner.y > 0. {
-                continue;
-            }
-
-            if corner_to_corner.x > corner_to_corner.y {
-                player_transform.translation.x -= barrier_to_player.x.signum() * corner_to_corner.x;
-            } else {
-                player_transform.translation.y -= barrier_to_player.y.signum() * corner_to_corner.y;
+            match barrier.kind {
+                BarrierKind::Solid => {
+                    player_transform.translation.x = resolved.x;
+                    player_transform.translation.y = resolved.y;
+                }
+                BarrierKind::Slow { owner } => {
+                    // The push out only moves players who overlap the tile
+                    if owner != player.handle && resolved != player_pos {
+                        slowed.0 = true;
+                    }
+                }
             }
         }
     }
@@ -102,22 +277,27 @@ pub fn handle_barrier_collisions(
 
 pub fn projectile_barrier_collisions(
     mut commands: Commands,
-    projectiles: Query<(Entity, &Transform), With<Projectile>>,
-    barriers: Query<(Entity, &Barrier, &Transform, &Sprite), (With<Barrier>, Without<Projectile>)>,
+    mut projectiles: Query<(Entity, &mut Projectile, &Transform)>,
+    mut barriers: Query<(Entity, &mut Barrier, &Transform, &Sprite), Without<Projectile>>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
 ) {
-    let half_map_limit = WORLD_SIZE as f32 * 0.5;
+    // Despawns are deferred, so remember which barriers already broke this frame
+    let mut broken_barriers = Vec::new();
 
-    for (proj_entity, proj_transform) in projectiles.iter() {
+    for (proj_entity, mut projectile, proj_transform) in &mut projectiles {
         let proj_pos = proj_transform.translation.xy();
 
-        // Remove projectile if it's beyond the map boundaries
-        if proj_pos.x.abs() > half_map_limit || proj_pos.y.abs() > half_map_limit {
-            commands.entity(proj_entity).despawn_recursive();
-            continue;
-        }
+        // Check collision with barriers, including the walls around the map
+        for (bar_entity, mut barrier_comp, bar_transform, bar_sprite) in barriers.iter_mut() {
+            if broken_barriers.contains(&bar_entity) {
+                continue;
+            }
+            // Projectiles fly over slow tiles, and lobbed ones over everything
+            if !barrier_comp.is_solid() || projectile.is_airborne() {
+                continue;
+            }
 
-        // Check collision with barriers
-        for (bar_entity, barrier_comp, bar_transform, bar_sprite) in barriers.iter() {
             let Some(bar_size) = bar_sprite.custom_size else {
                 panic!("Barrier is missing size information");
             };
@@ -127,14 +307,37 @@ pub fn projectile_barrier_collisions(
             let delta = proj_pos - bar_pos;
             let abs_delta = delta.abs();
 
-            // Determine overlap by subtracting half the barrier size
-            let overlap = abs_delta - (bar_size * 0.5);
+            // Determine overlap by subtracting half the barrier size and the projectile's radius
+            let overlap = abs_delta - (bar_size * 0.5 + Vec2::splat(settings.projectile_radius));
 
             // Check if projectile is inside the barrier
             if overlap.x <= 0.0 && overlap.y <= 0.0 {
-                // Despawn the barrier if it was placed by a player
-                if barrier_comp.player_placed {
+                // Grenades go off against the barrier, and the explosion deals with it
+                if projectile.is_explosive() {
+                    projectile.detonate();
+                    break;
+                }
+
+                // Piercing projectiles punch straight through placed barriers
+                // whatever their durability, using up a charge each. Anything
+                // else breaks the barrier once it has taken as many shots as
+                // the shooter needs, which depends on who placed it.
+                let Some(hits) = barrier_comp.hits_to_break(projectile.owner, &settings) else {
+                    commands.entity(proj_entity).despawn_recursive();
+                    break;
+                };
+                let pierces = projectile.piercing > 0;
+                barrier_comp.hits_taken += 1;
+                if pierces || barrier_comp.hits_taken >= hits {
                     commands.entity(bar_entity).despawn_recursive();
+                    broken_barriers.push(bar_entity);
+                    if let Some(placer) = barrier_comp.placed_by {
+                        outcome.destroy_barrier(projectile.owner, placer);
+                    }
+                }
+                if pierces {
+                    projectile.piercing -= 1;
+                    break;
                 }
                 // Despawn the projectile upon collision
                 commands.entity(proj_entity).despawn_recursive();
@@ -144,35 +347,259 @@ pub fn projectile_barrier_collisions(
     }
 }
 
+/// Whether players are allowed to place barriers this match
+pub fn building_enabled(settings: Res<GameSettings>) -> bool {
+    settings.building_enabled
+}
+
+/// Places a barrier or slow tile on the cell a player clicks
+///
+/// Holding the click only places one barrier, and dragging it places one on
+/// each new cell. Cells already covered by a barrier are left alone, so
+/// barriers never stack and each one takes the hits its durability allows.
 pub fn place_barrier_on_click(
     mut commands: Commands,
     inputs: Res<PlayerInputs<GameConfig>>,
-    players: Query<&Player>,
+    mut players: Query<(&Player, &mut HeldClick)>,
+    barriers: Query<(&Transform, &Sprite), With<Barrier>>,
+    mut pending_events: ResMut<PendingEvents>,
+    frame: Res<RollbackFrameCount>,
+    settings: Res<GameSettings>,
 ) {
-    for player in &players {
+    // Spawns are deferred, so remember the cells filled this frame
+    let mut placed_cells = Vec::new();
+
+    for (player, mut held_click) in &mut players {
         let (input, _) = inputs[player.handle];
+        let click = get_click_position(input);
+        let newly_placed = click.is_some() && click != held_click.0;
+        held_click.0 = click;
+
         // check_mouse_click returns Some((cell_x as u8, cell_y as u8))
-        if let Some((cell_x, cell_y)) = get_click_position(input) {
-            let size = Vec2::new(1., 1.);
+        let Some((cell_x, cell_y)) = click.filter(|_| newly_placed) else {
+            continue;
+        };
+        let size = Vec2::new(1., 1.);
+        let center = cell_center(UVec2::new(cell_x as u32, cell_y as u32));
 
-            commands.spawn((
-                Barrier {
-                    player_placed: true,
-                },
-                SpriteBundle {
-                    sprite: Sprite {
-                        color: player.color,
-                        custom_size: Some(size),
-                        ..default()
-                    },
-                    transform: Transform::from_translation(Vec3::new(
-                        cell_x as f32 - WORLD_SIZE as f32 / 2. + size.x / 2.,
-                        cell_y as f32 - WORLD_SIZE as f32 / 2. + size.y / 2.,
-                        10.,
-                    )),
+        let occupied = barriers.iter().any(|(transform, sprite)| {
+            let half_size = sprite.custom_size.expect("Barrier has no size") / 2.;
+            (center - transform.translation.xy()).abs().cmplt(half_size).all()
+        });
+        if occupied || placed_cells.contains(&center) {
+            continue;
+        }
+        placed_cells.push(center);
+
+        // Slow tiles are see-through and drawn under players
+        let (kind, color, z) = if is_placing_slow_tile(input) {
+            (
+                BarrierKind::Slow { owner: player.handle },
+                player.color.with_alpha(SLOW_TILE_ALPHA),
+                5.,
+            )
+        } else {
+            (BarrierKind::Solid, player.color, 10.)
+        };
+
+        let mut barrier = commands.spawn((
+            Barrier {
+                placed_by: Some(player.handle),
+                kind,
+                hits_taken: 0,
+            },
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(size),
                     ..default()
                 },
-            )).add_rollback();
+                transform: Transform::from_translation(center.extend(z)),
+                ..default()
+            },
+        ));
+        if settings.barrier_lifetime > 0.0 {
+            barrier.insert(BarrierDecay(FrameTimer::from_seconds(settings.barrier_lifetime, settings.tick_rate)));
+        }
+        barrier.add_rollback();
+        pending_events.push(&frame, BarrierPlaced { owner: player.handle });
+    }
+}
+
+/// Removes player-placed barriers whose lifetime has run out
+///
+/// Placing barriers isn't limited, so there's nothing to hand back to the placer.
+pub fn decay_barriers(mut commands: Commands, mut barriers: Query<(Entity, &mut BarrierDecay)>) {
+    for (entity, mut decay) in &mut barriers {
+        if decay.tick() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+    use crate::determinism::SimulationHarness;
+    use crate::player_module::{
+        check_player_collisions, round_outcome_world, Health, MovementDirection, MELEE_KNOCKBACK_DISTANCE,
+    };
+    use crate::projectile::Weapon;
+
+    /// Walks into the top left corner and keeps pushing against it
+    fn walk_into_corner(_frame: u32) -> u64 {
+        INPUT_UP | INPUT_LEFT | INPUT_READY
+    }
+
+    #[test]
+    fn players_cannot_leave_through_the_boundary_walls() {
+        let settings = GameSettings::default();
+        let limit = WORLD_SIZE as f32 / 2. - settings.player_radius + 1e-3;
+        let step = Duration::from_secs_f64(0.5 / settings.tick_rate as f64);
+        let mut harness = SimulationHarness::new(settings, walk_into_corner, step);
+
+        // Long enough to cross the whole map several times over
+        for frame in (30..=900).step_by(30) {
+            harness.run_until_frame(frame).unwrap();
+            for (handle, position) in harness.player_positions() {
+                assert!(
+                    position.x.abs() <= limit && position.y.abs() <= limit,
+                    "player {handle} got out to {position} by frame {frame}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn knockback_cannot_push_players_through_the_boundary_walls() {
+        let settings = GameSettings::default();
+        let limit = WORLD_SIZE as f32 / 2. - settings.player_radius + 1e-3;
+        let mut world = round_outcome_world(settings);
+        spawn_boundary_walls(&mut world.commands());
+        world.flush();
+
+        // Pressed against the right wall and shot from the left
+        let player = world
+            .spawn((
+                Player {
+                    speed: 1.0,
+                    handle: 0,
+                    color: Color::WHITE,
+                },
+                Health(100),
+                Transform::from_xyz(limit, 0., 0.),
+            ))
+            .id();
+        for _ in 0..5 {
+            world.spawn((pistol_round(1), MovementDirection(Vec2::X), Transform::from_xyz(limit, 0., 0.)));
+            world.run_system_once(check_player_collisions);
+            let position = world.get::<Transform>(player).unwrap().translation;
+            assert!(position.x <= limit, "knocked out to {position}");
+        }
+    }
+
+    #[test]
+    fn wrapping_edges_leave_out_the_walls_and_bring_players_back_in() {
+        let settings = GameSettings {
+            wrap_edges: true,
+            ..default()
+        };
+        // Players are wrapped as they move, so a knockback can only carry
+        // them past the edge until their next move
+        let limit = WORLD_SIZE as f32 / 2. + MELEE_KNOCKBACK_DISTANCE;
+        let step = Duration::from_secs_f64(0.5 / settings.tick_rate as f64);
+        let mut harness = SimulationHarness::new(settings, walk_into_corner, step);
+
+        for frame in (30..=900).step_by(30) {
+            harness.run_until_frame(frame).unwrap();
+            for (handle, position) in harness.player_positions() {
+                assert!(
+                    position.x.abs() <= limit && position.y.abs() <= limit,
+                    "player {handle} got out to {position} by frame {frame}"
+                );
+            }
+        }
+
+        let half_world_size = WORLD_SIZE as f32 / 2.;
+        let world = harness.world_mut();
+        let walls_outside_the_map = world
+            .query_filtered::<&Transform, With<Barrier>>()
+            .iter(world)
+            .filter(|transform| transform.translation.xy().abs().max_element() > half_world_size)
+            .count();
+        assert_eq!(walls_outside_the_map, 0);
+    }
+
+    /// A pistol round fired by `owner`, flying along the ground
+    fn pistol_round(owner: usize) -> Projectile {
+        Projectile {
+            owner,
+            damage: Weapon::Pistol.damage(),
+            ignites: false,
+            piercing: 0,
+            velocity: Vec2::X,
+            height: 0.0,
+            vertical_speed: 0.0,
+            gravity: 0.0,
+            fuse: None,
+            wraps_left: 0,
         }
     }
-}
\ No newline at end of file
+
+    /// Shoots one projectile from `shooter` into the barrier at the origin
+    fn shoot_barrier(world: &mut World, shooter: usize) {
+        world.spawn((pistol_round(shooter), Transform::default()));
+        world.run_system_once(projectile_barrier_collisions);
+    }
+
+    fn barrier_count(world: &mut World) -> usize {
+        world.query::<&Barrier>().iter(world).count()
+    }
+
+    #[test]
+    fn enemy_barriers_break_at_the_enemy_hit_count() {
+        let settings = GameSettings {
+            own_barrier_hits: 5,
+            enemy_barrier_hits: 3,
+            ..default()
+        };
+        let mut world = round_outcome_world(settings);
+        world.spawn((
+            Barrier {
+                placed_by: Some(1),
+                kind: BarrierKind::Solid,
+                hits_taken: 0,
+            },
+            Sprite {
+                custom_size: Some(Vec2::ONE),
+                ..default()
+            },
+            Transform::default(),
+        ));
+
+        for _ in 0..2 {
+            shoot_barrier(&mut world, 0);
+            assert_eq!(barrier_count(&mut world), 1);
+        }
+        shoot_barrier(&mut world, 0);
+        assert_eq!(barrier_count(&mut world), 0);
+        // Every projectile is used up by the hit that stopped it
+        assert_eq!(world.query::<&Projectile>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn map_walls_never_break() {
+        let mut world = round_outcome_world(GameSettings::default());
+        spawn_boundary_walls(&mut world.commands());
+        world.flush();
+        let walls = barrier_count(&mut world);
+
+        for _ in 0..10 {
+            world.spawn((pistol_round(0), Transform::from_xyz(WORLD_SIZE as f32 / 2. + 1., 0., 0.)));
+            world.run_system_once(projectile_barrier_collisions);
+        }
+        assert_eq!(barrier_count(&mut world), walls);
+    }
+}
@@ -0,0 +1,256 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_ggrs::{LocalInputs, LocalPlayers, ReadInputs};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use crate::{AppState, GameConfig};
+use crate::input_handler::{collect_player_inputs, encode_analog, INPUT_READY, INPUT_SHOOT};
+use crate::network_manager::{LocalRole, Opponents, RandomSeed, NUM_PLAYERS};
+use crate::player_module::Player;
+use crate::practice::PracticeRange;
+use crate::projectile::PROJECTILE_SPEED;
+use crate::replay::ReplayPlayback;
+use crate::settings::{AiDifficulty, GameSettings};
+
+/// Analog magnitude bots use to turn on the spot while keeping their distance
+const TURN_IN_PLACE_MAGNITUDE: f32 = 0.2;
+
+/// How a bot plays at each difficulty
+struct BotTuning {
+    /// Frames between a bot re-reading the situation
+    reaction_frames: u32,
+    /// Largest random error added to the bot's aim, in radians
+    aim_error: f32,
+    /// Chance of firing each time the bot is able to
+    fire_chance: f64,
+    /// Frames a bot waits between shots
+    fire_cooldown_frames: u32,
+    /// Whether the bot aims where the target is heading instead of where it is
+    leads_target: bool,
+    /// Distance the bot closes to before it stops advancing
+    preferred_distance: f32,
+}
+
+fn tuning(difficulty: AiDifficulty) -> BotTuning {
+    match difficulty {
+        AiDifficulty::Easy => BotTuning {
+            reaction_frames: 30,
+            aim_error: 0.5,
+            fire_chance: 0.3,
+            fire_cooldown_frames: 45,
+            leads_target: false,
+            preferred_distance: 14.0,
+        },
+        AiDifficulty::Normal => BotTuning {
+            reaction_frames: 15,
+            aim_error: 0.2,
+            fire_chance: 0.6,
+            fire_cooldown_frames: 20,
+            leads_target: false,
+            preferred_distance: 9.0,
+        },
+        AiDifficulty::Hard => BotTuning {
+            reaction_frames: 6,
+            aim_error: 0.03,
+            fire_chance: 0.9,
+            fire_cooldown_frames: 8,
+            leads_target: true,
+            preferred_distance: 5.0,
+        },
+    }
+}
+
+/// What a bot last decided to do
+#[derive(Clone, Copy, Default)]
+struct BotState {
+    handle: usize,
+    frames_until_reaction: u32,
+    frames_until_fire: u32,
+    /// Direction and strength of the analog stick the bot holds
+    stick: Vec2,
+    /// Whether the bot will fire once its cooldown allows
+    wants_to_fire: bool,
+    /// Where the bot's target was on the previous frame
+    last_target_position: Option<Vec2>,
+}
+
+/// Players controlled by bots in a match against bots
+///
+/// Bot inputs are generated outside the rollback simulation like any other
+/// local input, from a generator seeded with the match seed, so the same seed
+/// and the same human inputs always produce the same bot behaviour.
+#[derive(Resource)]
+pub struct Bots {
+    rng: Xoshiro256PlusPlus,
+    bots: Vec<BotState>,
+}
+
+impl Bots {
+    pub fn contains(&self, handle: usize) -> bool {
+        self.bots.iter().any(|bot| bot.handle == handle)
+    }
+}
+
+/// The local players controlled by a person rather than a bot
+#[derive(SystemParam)]
+pub struct HumanPlayers<'w> {
+    local_players: Option<Res<'w, LocalPlayers>>,
+    bots: Option<Res<'w, Bots>>,
+    practice: Option<Res<'w, PracticeRange>>,
+    replay: Option<Res<'w, ReplayPlayback>>,
+}
+
+impl HumanPlayers<'_> {
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        // Every player in a replay is local, but none of them are controlled here
+        self.local_players
+            .iter()
+            .filter(|_| self.replay.is_none())
+            .flat_map(|local| local.0.iter().copied())
+            .filter(|&handle| !self.bots.as_ref().is_some_and(|bots| bots.contains(handle)))
+            .filter(|&handle| !self.practice.as_ref().is_some_and(|practice| practice.contains(handle)))
+    }
+
+    pub fn contains(&self, handle: usize) -> bool {
+        self.iter().any(|human| human == handle)
+    }
+
+    /// True for spectators, who don't control anyone
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+/// Registers the bot systems to the app
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(AppState::Connecting),
+        start_bot_session.run_if(resource_equals(Opponents::Bots)),
+    )
+    .add_systems(
+        ReadInputs,
+        collect_bot_inputs
+            .after(collect_player_inputs)
+            .run_if(resource_exists::<Bots>),
+    )
+    .add_systems(OnExit(AppState::GameOver), remove_bots);
+}
+
+/// Starts a local session where the first player is human and the rest are bots
+///
+/// Every handle is local, so a sync test session with no check distance runs
+/// the simulation without networking or rollbacks.
+pub fn start_bot_session(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<AppState>>,
+    settings: Res<GameSettings>,
+) {
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    commands.insert_resource(RandomSeed(seed));
+
+    let mut session_builder = ggrs::SessionBuilder::<GameConfig>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_fps(settings.tick_rate)
+        .expect("Tick rate was validated when parsing settings")
+        .with_check_distance(0);
+    for handle in 0..NUM_PLAYERS {
+        session_builder = session_builder
+            .add_player(ggrs::PlayerType::Local, handle)
+            .expect("Failed to add player to session");
+    }
+    let ggrs_session = session_builder
+        .start_synctest_session()
+        .expect("Failed to start bot session");
+
+    commands.insert_resource(Bots {
+        rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+        bots: (1..NUM_PLAYERS)
+            .map(|handle| BotState {
+                handle,
+                ..default()
+            })
+            .collect(),
+    });
+    commands.insert_resource(LocalRole::Player);
+    commands.insert_resource(bevy_ggrs::Session::SyncTest(ggrs_session));
+    next_state.set(AppState::InGame);
+}
+
+/// Generates each bot's input from the positions of the players
+pub(crate) fn collect_bot_inputs(
+    mut bots: ResMut<Bots>,
+    mut local_inputs: ResMut<LocalInputs<GameConfig>>,
+    players: Query<(&Player, &Transform)>,
+    settings: Res<GameSettings>,
+) {
+    let tuning = tuning(settings.ai_difficulty);
+    let Bots { rng, bots } = &mut *bots;
+
+    for bot in bots.iter_mut() {
+        let mut input = INPUT_READY;
+
+        let position = players
+            .iter()
+            .find(|(player, _)| player.handle == bot.handle)
+            .map(|(_, transform)| transform.translation.xy());
+        let target = position.and_then(|position| {
+            players
+                .iter()
+                .filter(|(player, _)| player.handle != bot.handle)
+                .map(|(_, transform)| transform.translation.xy())
+                .min_by(|a, b| position.distance(*a).total_cmp(&position.distance(*b)))
+                .map(|target_position| (position, target_position))
+        });
+
+        // Track the target every frame so its velocity is known when reacting
+        let target_velocity = match (target, bot.last_target_position) {
+            (Some((_, target_position)), Some(last)) => (target_position - last) / settings.frame_delta(),
+            _ => Vec2::ZERO,
+        };
+        bot.last_target_position = target.map(|(_, target_position)| target_position);
+
+        bot.frames_until_reaction = bot.frames_until_reaction.saturating_sub(1);
+        bot.frames_until_fire = bot.frames_until_fire.saturating_sub(1);
+
+        if bot.frames_until_reaction == 0 {
+            bot.frames_until_reaction = tuning.reaction_frames;
+            bot.stick = Vec2::ZERO;
+            bot.wants_to_fire = false;
+
+            if let Some((position, target_position)) = target {
+                let mut aim_point = target_position;
+                if tuning.leads_target {
+                    let travel_time = position.distance(target_position) / PROJECTILE_SPEED;
+                    aim_point += target_velocity * travel_time;
+                }
+
+                let error = rng.gen_range(-tuning.aim_error..=tuning.aim_error);
+                let aim = Vec2::from_angle(error).rotate((aim_point - position).normalize_or_zero());
+
+                // Shots fire along the facing, which follows movement, so
+                // bots in range creep towards the target just to face it
+                let magnitude = if position.distance(target_position) > tuning.preferred_distance {
+                    1.0
+                } else {
+                    TURN_IN_PLACE_MAGNITUDE
+                };
+                bot.stick = aim * magnitude;
+                bot.wants_to_fire = rng.gen_bool(tuning.fire_chance);
+            }
+        }
+
+        input |= encode_analog(bot.stick);
+
+        // Semi-automatic fire needs the trigger released between shots, so fire on single frames
+        if bot.wants_to_fire && bot.frames_until_fire == 0 {
+            input |= INPUT_SHOOT;
+            bot.frames_until_fire = tuning.fire_cooldown_frames;
+        }
+
+        local_inputs.0.insert(bot.handle, input);
+    }
+}
+
+fn remove_bots(mut commands: Commands) {
+    commands.remove_resource::<Bots>();
+}
@@ -0,0 +1,266 @@
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use rand::Rng;
+use crate::AppState;
+use crate::bots::HumanPlayers;
+use crate::chat::chat_closed;
+use crate::events::PlayerKilled;
+use crate::interpolation::{advance_interpolation, Interpolated, InterpolationFraction};
+use crate::network_manager::LocalRole;
+use crate::player_module::{Player, SpawnMarker};
+use crate::settings::GameSettings;
+
+/// World units per second the free camera pans
+const FREE_CAMERA_PAN_SPEED: f32 = 15.0;
+/// How much one wheel notch changes the zoom
+const FREE_CAMERA_ZOOM_STEP: f32 = 0.1;
+const MIN_CAMERA_ZOOM: f32 = 0.5;
+const MAX_CAMERA_ZOOM: f32 = 3.0;
+/// Largest camera offset of a full strength shake, in world units
+const SCREEN_SHAKE_MAGNITUDE: f32 = 0.4;
+/// Furthest a smoothed camera trails behind the player it follows, in world units
+const CAMERA_MAX_LAG: f32 = 3.0;
+/// Deaths further than this from the camera don't shake it
+const SCREEN_SHAKE_RADIUS: f32 = 12.0;
+
+/// How the camera decides where to look. This is purely local and never rolled back.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CameraMode {
+    /// Track the local player, or a survivor once they're dead
+    #[default]
+    FollowLocal,
+    /// Pan with WASD and zoom with the mouse wheel
+    Free,
+}
+
+/// A decaying shake of the local camera. Purely cosmetic, so it uses the
+/// thread RNG rather than anything from the simulation.
+#[derive(Resource, Default)]
+struct ScreenShake {
+    /// Seconds left until the shake has settled
+    remaining: f32,
+    /// Strength of the current shake, from 0 to 1
+    intensity: f32,
+    /// Offset applied to the camera last frame, undone before it moves again
+    offset: Vec2,
+}
+
+/// Registers the camera systems to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<CameraMode>()
+        .init_resource::<ScreenShake>()
+        .add_systems(Startup, spawn_camera)
+        .add_systems(OnEnter(AppState::InGame), choose_camera_mode)
+        .add_systems(OnExit(AppState::GameOver), reset_camera)
+        .add_systems(
+            Update,
+            (
+                remove_screen_shake,
+                (
+                    camera_follow
+                        .run_if(resource_equals(CameraMode::FollowLocal))
+                        .after(advance_interpolation),
+                    free_camera
+                        .run_if(resource_equals(CameraMode::Free))
+                        .run_if(chat_closed),
+                ),
+                (start_screen_shake, apply_screen_shake).chain(),
+            )
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        );
+}
+
+/// Spawns the main camera, which is kept across matches
+fn spawn_camera(mut commands: Commands) {
+    // Set up the main camera with fixed vertical scaling
+    let mut camera_bundle = Camera2dBundle::default();
+    camera_bundle.projection.scaling_mode = ScalingMode::FixedVertical(15.0);
+    commands.spawn((camera_bundle, Name::new("Main Camera")));
+}
+
+/// Uses the free camera for peers that aren't controlling a player
+fn choose_camera_mode(local_role: Res<LocalRole>, mut camera_mode: ResMut<CameraMode>) {
+    *camera_mode = match *local_role {
+        LocalRole::Player => CameraMode::FollowLocal,
+        LocalRole::Spectator => CameraMode::Free,
+    };
+}
+
+/// Recenters the camera and undoes any zoom between matches
+fn reset_camera(
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    mut shake: ResMut<ScreenShake>,
+) {
+    *shake = ScreenShake::default();
+    for (mut transform, mut projection) in &mut camera_query {
+        transform.translation.x = 0.;
+        transform.translation.y = 0.;
+        projection.scale = 1.0;
+    }
+}
+
+/// Makes the camera follow the local player_module
+///
+/// Once the local player is dead the camera follows the surviving player with
+/// the lowest handle until the next round respawns them. This is worked out
+/// from the players that exist each frame rather than a flag set by the
+/// rollback simulation, so a mispredicted death can't leave it stuck.
+fn camera_follow(
+    humans: HumanPlayers,
+    player_query: Query<(&Player, &Transform, Option<&Interpolated>)>,
+    spawn_markers: Query<(&SpawnMarker, &Transform)>,
+    mut camera_query: Query<&mut Transform, (With<Camera>, Without<Player>, Without<SpawnMarker>)>,
+    fraction: Res<InterpolationFraction>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+) {
+    let local_player = player_query
+        .iter()
+        .find(|(player, _, _)| humans.contains(player.handle));
+
+    // Before the round's players appear, look at where the local player will spawn
+    let local_spawn = spawn_markers
+        .iter()
+        .find(|(marker, _)| humans.contains(marker.handle))
+        .map(|(_, transform)| transform.translation.xy());
+
+    let target = local_player.or_else(|| {
+        player_query
+            .iter()
+            .min_by_key(|(player, _, _)| player.handle)
+    });
+
+    // Track where the player is drawn so the camera moves as smoothly as they do
+    let position = if let (None, Some(spawn)) = (local_player, local_spawn) {
+        spawn
+    } else if let Some((_, player_transform, interpolated)) = target {
+        interpolated.map_or(player_transform.translation.xy(), |interpolated| {
+            interpolated.position(&fraction)
+        })
+    } else {
+        return;
+    };
+
+    // Smoothing eases towards the target, framerate independently, but never
+    // lets it get far away
+    let lag = if settings.reduced_motion { 0.0 } else { settings.camera_lag };
+    for mut transform in &mut camera_query {
+        let followed = if lag > 0.0 {
+            let eased = transform.translation.xy().lerp(position, 1.0 - (-time.delta_seconds() / lag).exp());
+            position + (eased - position).clamp_length_max(CAMERA_MAX_LAG)
+        } else {
+            position
+        };
+        transform.translation.x = followed.x;
+        transform.translation.y = followed.y;
+    }
+}
+
+/// Pans the camera with WASD and zooms it with the mouse wheel
+fn free_camera(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    time: Res<Time>,
+) {
+    let mut pan = Vec2::ZERO;
+    if keyboard_input.pressed(KeyCode::KeyW) {
+        pan.y += 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyS) {
+        pan.y -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyA) {
+        pan.x -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyD) {
+        pan.x += 1.0;
+    }
+
+    // Pixel-based scrolling (touchpads) reports much larger values than notches
+    let scroll: f32 = wheel_events
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 100.0,
+        })
+        .sum();
+
+    for (mut transform, mut projection) in &mut camera_query {
+        projection.scale = (projection.scale * (1.0 - scroll * FREE_CAMERA_ZOOM_STEP))
+            .clamp(MIN_CAMERA_ZOOM, MAX_CAMERA_ZOOM);
+
+        // Pan faster when zoomed out so crossing the map takes the same time on screen
+        let delta = pan.normalize_or_zero()
+            * FREE_CAMERA_PAN_SPEED
+            * projection.scale
+            * time.delta_seconds();
+        transform.translation.x += delta.x;
+        transform.translation.y += delta.y;
+    }
+}
+
+/// Shakes the camera when the local player dies or someone dies close to the camera
+fn start_screen_shake(
+    mut kills: EventReader<PlayerKilled>,
+    mut shake: ResMut<ScreenShake>,
+    humans: HumanPlayers,
+    camera_query: Query<&Transform, With<Camera>>,
+    settings: Res<GameSettings>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let camera_position = camera_transform.translation.xy() - shake.offset;
+
+    for kill in kills.read() {
+        let intensity = if humans.contains(kill.victim) {
+            1.0
+        } else {
+            1.0 - kill.position.distance(camera_position) / SCREEN_SHAKE_RADIUS
+        };
+        if intensity <= 0.0 || settings.screen_shake_strength <= 0.0 || settings.reduced_motion {
+            continue;
+        }
+
+        shake.intensity = shake.intensity.max(intensity);
+        shake.remaining = settings.screen_shake_duration;
+    }
+}
+
+/// Undoes last frame's shake so the camera systems see the real camera position
+fn remove_screen_shake(mut shake: ResMut<ScreenShake>, mut camera_query: Query<&mut Transform, With<Camera>>) {
+    for mut transform in &mut camera_query {
+        transform.translation.x -= shake.offset.x;
+        transform.translation.y -= shake.offset.y;
+    }
+    shake.offset = Vec2::ZERO;
+}
+
+/// Offsets the camera randomly, fading out over the shake's duration
+fn apply_screen_shake(
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<Camera>>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+) {
+    if shake.remaining <= 0.0 {
+        return;
+    }
+
+    shake.remaining = (shake.remaining - time.delta_seconds()).max(0.0);
+    let fade = (shake.remaining / settings.screen_shake_duration).powi(2);
+    let magnitude = SCREEN_SHAKE_MAGNITUDE * settings.screen_shake_strength * shake.intensity * fade;
+    if shake.remaining == 0.0 {
+        shake.intensity = 0.0;
+    }
+
+    let mut rng = rand::thread_rng();
+    shake.offset = Vec2::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * magnitude;
+    for mut transform in &mut camera_query {
+        transform.translation.x += shake.offset.x;
+        transform.translation.y += shake.offset.y;
+    }
+}
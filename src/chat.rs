@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use crate::AppState;
+use crate::network_manager::{PeerHandles, CHAT_CHANNEL, NUM_PLAYERS};
+
+/// How many messages are shown at once
+const CHAT_HISTORY_LENGTH: usize = 6;
+/// Longest message that can be typed or received, in characters
+const MAX_CHAT_MESSAGE_LENGTH: usize = 120;
+
+/// The local chat box. Chat travels over its own reliable socket channel and
+/// never goes near the rollback simulation.
+#[derive(Resource, Default)]
+pub struct ChatState {
+    /// Whether the player is typing a message, which takes over the keyboard
+    open: bool,
+    draft: String,
+}
+
+impl ChatState {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+/// Messages sent and received this match, oldest first
+#[derive(Resource, Default)]
+struct ChatLog {
+    messages: VecDeque<ChatMessage>,
+}
+
+struct ChatMessage {
+    sender: String,
+    text: String,
+}
+
+impl ChatLog {
+    fn push(&mut self, sender: String, text: String) {
+        if self.messages.len() >= CHAT_HISTORY_LENGTH {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(ChatMessage { sender, text });
+    }
+}
+
+/// Text showing the chat history and the message being typed
+#[derive(Component)]
+struct ChatBox;
+
+/// Registers the chat systems to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<ChatState>()
+        .init_resource::<ChatLog>()
+        .add_systems(OnEnter(AppState::InGame), spawn_chat_box)
+        .add_systems(OnExit(AppState::InGame), close_chat)
+        .add_systems(
+            Update,
+            (
+                type_chat,
+                receive_chat.run_if(resource_exists::<MatchboxSocket<MultipleChannels>>),
+                update_chat_box,
+            )
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        );
+}
+
+/// Run condition for systems reading the keyboard, which is ignored while typing
+pub fn chat_closed(chat: Res<ChatState>) -> bool {
+    !chat.open
+}
+
+fn spawn_chat_box(mut commands: Commands) {
+    commands.spawn((
+        ChatBox,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            left: Val::Px(12.0),
+            max_width: Val::Percent(40.0),
+            ..default()
+        }),
+    ));
+}
+
+fn close_chat(
+    mut commands: Commands,
+    mut chat: ResMut<ChatState>,
+    mut log: ResMut<ChatLog>,
+    chat_box: Query<Entity, With<ChatBox>>,
+) {
+    *chat = ChatState::default();
+    *log = ChatLog::default();
+    for entity in &chat_box {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Opens the chat box with Enter, and sends the typed message with Enter again
+fn type_chat(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut chat: ResMut<ChatState>,
+    mut log: ResMut<ChatLog>,
+    mut socket: Option<ResMut<MatchboxSocket<MultipleChannels>>>,
+) {
+    for event in keyboard_events.read() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+
+        match (&event.logical_key, chat.open) {
+            (Key::Enter, false) => chat.open = true,
+            (Key::Enter, true) => {
+                let text = std::mem::take(&mut chat.draft).trim().to_string();
+                chat.open = false;
+                if text.is_empty() {
+                    continue;
+                }
+
+                // Bot matches have no socket, so the message only shows locally
+                if let Some(socket) = socket.as_mut() {
+                    let peers: Vec<PeerId> = socket.connected_peers().collect();
+                    let channel = socket.channel_mut(CHAT_CHANNEL);
+                    for peer in peers {
+                        channel.send(text.as_bytes().into(), peer);
+                    }
+                }
+                log.push(String::from("You"), text);
+            }
+            (Key::Escape, true) => {
+                chat.open = false;
+                chat.draft.clear();
+            }
+            (Key::Backspace, true) => {
+                chat.draft.pop();
+            }
+            (Key::Space, true) => push_to_draft(&mut chat.draft, " "),
+            (Key::Character(characters), true) => push_to_draft(&mut chat.draft, characters),
+            _ => {}
+        }
+    }
+}
+
+fn push_to_draft(draft: &mut String, characters: &str) {
+    for character in characters.chars() {
+        if draft.chars().count() >= MAX_CHAT_MESSAGE_LENGTH {
+            return;
+        }
+        draft.push(character);
+    }
+}
+
+/// Adds messages from other peers to the chat log
+fn receive_chat(
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut log: ResMut<ChatLog>,
+    peer_handles: Option<Res<PeerHandles>>,
+) {
+    for (peer, packet) in socket.channel_mut(CHAT_CHANNEL).receive() {
+        let sender = match peer_handles.as_ref().and_then(|handles| handles.get(&peer)) {
+            Some(&handle) if handle < NUM_PLAYERS => format!("Player {}", handle + 1),
+            _ => String::from("Spectator"),
+        };
+        let text: String = String::from_utf8_lossy(&packet)
+            .chars()
+            .take(MAX_CHAT_MESSAGE_LENGTH)
+            .collect();
+        log.push(sender, text);
+    }
+}
+
+fn update_chat_box(
+    chat: Res<ChatState>,
+    log: Res<ChatLog>,
+    mut chat_box: Query<&mut Text, With<ChatBox>>,
+) {
+    if !chat.is_changed() && !log.is_changed() {
+        return;
+    }
+
+    let mut lines: Vec<String> = log
+        .messages
+        .iter()
+        .map(|message| format!("{}: {}", message.sender, message.text))
+        .collect();
+    if chat.open {
+        lines.push(format!("> {}_", chat.draft));
+    }
+
+    for mut text in &mut chat_box {
+        text.sections[0].value = lines.join("\n");
+    }
+}
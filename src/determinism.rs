@@ -0,0 +1,322 @@
+use std::time::Duration;
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::HashMap;
+use bevy_ggrs::{LocalInputs, ReadInputs, RollbackFrameCount, Session};
+use crate::{add_simulation, AppState, GameConfig, GameTextures, WORLD_SIZE};
+use crate::barriers::Barrier;
+use crate::bots::{collect_bot_inputs, start_bot_session};
+use crate::input_handler::{
+    encode_cursor_cell, INPUT_CLICK, INPUT_DOWN, INPUT_LEFT, INPUT_READY, INPUT_RIGHT, INPUT_SHOOT,
+    INPUT_UP,
+};
+use crate::maps::GameMaps;
+use crate::network_manager::{Opponents, NUM_PLAYERS};
+use crate::player_module::{Health, Player};
+use crate::projectile::{Ammo, Projectile};
+use crate::settings::GameSettings;
+use crate::utilities::{GameRng, PlayerScores};
+
+// Every peer runs the rollback simulation on its own and only inputs are sent
+// between them, so the whole game relies on the same inputs producing the
+// same state everywhere. This runs a bot match twice in headless apps, with
+// the same seed and inputs but different render frame timings, and compares
+// where everything ended up. Anything that reads the frame time, depends on
+// system order that isn't pinned down or draws on an unseeded generator
+// shows up as a difference.
+//
+// Each run also simulates the last few frames again every frame, like a
+// rollback would, and compares checksums of the two attempts. State that
+// isn't rolled back properly makes them differ, which stalls the session.
+
+/// Match seed used when the settings don't fix one
+const DEFAULT_CHECK_SEED: u64 = 1905;
+/// Updates allowed per simulated frame before giving up on a stalled simulation
+const MAX_UPDATES_PER_FRAME: u32 = 10;
+/// Frames simulated again every frame to check rollbacks land on the same state
+const CHECK_DISTANCE: usize = 2;
+
+/// Input the human player sends on each simulated frame
+pub type InputScript = fn(u32) -> u64;
+
+#[derive(Resource)]
+struct ScriptedInputs {
+    script: InputScript,
+    next_frame: u32,
+}
+
+/// A bot match simulated without a window, renderer or network, where
+/// player 1 follows an input script
+pub struct SimulationHarness {
+    app: App,
+}
+
+impl SimulationHarness {
+    /// Builds the match, advancing the clock by `step` every update
+    pub fn new(mut settings: GameSettings, script: InputScript, step: Duration) -> Self {
+        settings.seed.get_or_insert(DEFAULT_CHECK_SEED);
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, AssetPlugin::default()))
+            .init_asset::<Image>();
+        add_simulation(&mut app, settings);
+
+        // Nothing is drawn, so empty handles stand in for the textures, and
+        // preset maps fall back to random walls
+        app.insert_resource(GameTextures::default())
+            .insert_resource(GameMaps::default())
+            .insert_resource(Opponents::Bots)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(step))
+            .insert_resource(ScriptedInputs {
+                script,
+                next_frame: 0,
+            })
+            .add_systems(OnEnter(AppState::Connecting), start_checked_session.after(start_bot_session))
+            .add_systems(ReadInputs, read_scripted_inputs.before(collect_bot_inputs));
+        app.finish();
+        app.cleanup();
+
+        app.world_mut()
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::Connecting);
+
+        Self { app }
+    }
+
+    /// Last frame the simulation ran
+    pub fn frame(&self) -> i32 {
+        self.app
+            .world()
+            .get_resource::<RollbackFrameCount>()
+            .map_or(0, |frame| frame.0)
+    }
+
+    /// Updates the app until the simulation has run `frame` frames
+    pub fn run_until_frame(&mut self, frame: i32) -> Result<(), String> {
+        let max_updates = MAX_UPDATES_PER_FRAME * frame.max(1) as u32;
+        for _ in 0..max_updates {
+            if self.frame() >= frame {
+                return Ok(());
+            }
+            self.app.update();
+        }
+
+        Err(format!(
+            "Simulation stalled at frame {} of {frame}, likely on a checksum mismatch after a rollback",
+            self.frame()
+        ))
+    }
+
+    /// The simulated world, for checking state the snapshot leaves out
+    pub fn world_mut(&mut self) -> &mut World {
+        self.app.world_mut()
+    }
+
+    /// Where every player still alive stands, by handle
+    pub fn player_positions(&mut self) -> Vec<(usize, Vec2)> {
+        let world = self.app.world_mut();
+        world
+            .query::<(&Player, &Transform)>()
+            .iter(world)
+            .map(|(player, transform)| (player.handle, transform.translation.xy()))
+            .collect()
+    }
+
+    /// Records the state that must match between peers
+    pub fn snapshot(&mut self) -> SimulationSnapshot {
+        let frame = self.frame();
+        let world = self.app.world_mut();
+
+        let mut players: Vec<PlayerSnapshot> = world
+            .query::<(&Player, &Transform, &Health, &Ammo)>()
+            .iter(world)
+            .map(|(player, transform, health, ammo)| PlayerSnapshot {
+                handle: player.handle,
+                position: position_bits(transform),
+                health: health.0,
+                ammo: *ammo,
+            })
+            .collect();
+        players.sort_by_key(|player| player.handle);
+
+        let mut projectiles: Vec<[u32; 3]> = world
+            .query_filtered::<&Transform, With<Projectile>>()
+            .iter(world)
+            .map(position_bits)
+            .collect();
+        projectiles.sort();
+
+        let mut barriers: Vec<[u32; 3]> = world
+            .query_filtered::<&Transform, With<Barrier>>()
+            .iter(world)
+            .map(position_bits)
+            .collect();
+        barriers.sort();
+
+        let scores = world.get_resource::<PlayerScores>().map(|scores| {
+            (0..NUM_PLAYERS)
+                .map(|player| scores.get(player))
+                .collect()
+        });
+
+        let rng = world.get_resource::<GameRng>().map(GameRng::fingerprint);
+
+        SimulationSnapshot {
+            frame,
+            players,
+            projectiles,
+            barriers,
+            scores,
+            rng,
+        }
+    }
+}
+
+/// Positions are compared bit for bit, since even the smallest difference
+/// between peers grows into a desync
+fn position_bits(transform: &Transform) -> [u32; 3] {
+    transform.translation.to_array().map(f32::to_bits)
+}
+
+/// The simulated state of a player still alive
+#[derive(Debug, PartialEq, Eq)]
+struct PlayerSnapshot {
+    handle: usize,
+    position: [u32; 3],
+    health: u32,
+    ammo: Ammo,
+}
+
+/// The simulated state of a match at one frame
+#[derive(Debug, PartialEq, Eq)]
+pub struct SimulationSnapshot {
+    frame: i32,
+    players: Vec<PlayerSnapshot>,
+    projectiles: Vec<[u32; 3]>,
+    barriers: Vec<[u32; 3]>,
+    scores: Option<Vec<u64>>,
+    /// Next number the simulation's generator would draw
+    rng: Option<u64>,
+}
+
+impl SimulationSnapshot {
+    /// Names the parts of the state that differ from `other`
+    fn differences(&self, other: &SimulationSnapshot) -> Vec<&'static str> {
+        let mut differences = Vec::new();
+        if self.frame != other.frame {
+            differences.push("frame");
+        }
+        if self.players != other.players {
+            differences.push("players");
+        }
+        if self.projectiles != other.projectiles {
+            differences.push("projectiles");
+        }
+        if self.barriers != other.barriers {
+            differences.push("barriers");
+        }
+        if self.scores != other.scores {
+            differences.push("scores");
+        }
+        if self.rng != other.rng {
+            differences.push("random generator");
+        }
+        differences
+    }
+}
+
+/// Simulates `frames` frames of the same bot match twice and checks both
+/// runs end in exactly the same state
+pub fn check(settings: GameSettings, frames: u32) -> Result<(), String> {
+    let frame_duration = Duration::from_secs_f64(1.0 / settings.tick_rate as f64);
+
+    // Render frames shorter than a simulation frame never run two simulation
+    // frames in one update, so both runs stop exactly on the last frame
+    let mut first = SimulationHarness::new(settings.clone(), wander, frame_duration / 2);
+    let mut second = SimulationHarness::new(settings, wander, frame_duration / 3);
+
+    first.run_until_frame(frames as i32)?;
+    second.run_until_frame(frames as i32)?;
+
+    let first = first.snapshot();
+    let second = second.snapshot();
+    let differences = first.differences(&second);
+    if differences.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "Simulations diverged in {} by frame {frames}\nFirst run: {first:?}\nSecond run: {second:?}",
+        differences.join(", ")
+    ))
+}
+
+/// Walks in a new direction every half second, taps fire regularly and
+/// places a barrier every so often
+fn wander(frame: u32) -> u64 {
+    const DIRECTIONS: [u64; 8] = [
+        INPUT_UP,
+        INPUT_UP | INPUT_RIGHT,
+        INPUT_RIGHT,
+        INPUT_DOWN | INPUT_RIGHT,
+        INPUT_DOWN,
+        INPUT_DOWN | INPUT_LEFT,
+        INPUT_LEFT,
+        INPUT_UP | INPUT_LEFT,
+    ];
+
+    let mut input = INPUT_READY | DIRECTIONS[(frame / 30 % 8) as usize];
+
+    // Semi-automatic fire needs the trigger released between shots
+    if frame % 20 < 2 {
+        input |= INPUT_SHOOT;
+    }
+
+    if frame % 90 == 0 {
+        let placement = frame / 90;
+        input |= INPUT_CLICK | encode_cursor_cell(placement * 7 % WORLD_SIZE, placement * 13 % WORLD_SIZE);
+    }
+
+    input
+}
+
+/// Replaces the bot session with one that checks every frame against a
+/// second simulation of it
+fn start_checked_session(mut commands: Commands, settings: Res<GameSettings>) {
+    let mut session_builder = ggrs::SessionBuilder::<GameConfig>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_fps(settings.tick_rate)
+        .expect("Tick rate was validated when parsing settings")
+        .with_check_distance(CHECK_DISTANCE);
+    for handle in 0..NUM_PLAYERS {
+        session_builder = session_builder
+            .add_player(ggrs::PlayerType::Local, handle)
+            .expect("Failed to add player to session");
+    }
+    let ggrs_session = session_builder
+        .start_synctest_session()
+        .expect("Failed to start checked session");
+    commands.insert_resource(Session::SyncTest(ggrs_session));
+}
+
+fn read_scripted_inputs(mut commands: Commands, mut scripted: ResMut<ScriptedInputs>) {
+    let input = (scripted.script)(scripted.next_frame);
+    scripted.next_frame += 1;
+
+    // Only the first player is scripted, bots fill in the rest
+    let mut inputs = HashMap::new();
+    inputs.insert(0, input);
+    commands.insert_resource(LocalInputs::<GameConfig>(inputs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_match_is_deterministic() {
+        check(GameSettings::default(), 600).unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+use bevy::prelude::*;
+use bevy_ggrs::ConfirmedFrameCount;
+use rand::Rng;
+use crate::{AppState, WORLD_SIZE};
+use crate::barriers::{building_enabled, ray_box_entry, Barrier, BarrierDecay};
+use crate::bots::HumanPlayers;
+use crate::events::{GrenadeExploded, PlayerKilled};
+use crate::input_handler::PlacementSelector;
+use crate::interpolation::{advance_interpolation, Interpolated, InterpolationFraction};
+use crate::player_module::{
+    player_color, Burning, MeleeCooldown, MovementDirection, Player, GRENADE_BLAST_RADIUS, MELEE_COOLDOWN_SECONDS,
+    MELEE_HALF_ARC_DEGREES, MELEE_RANGE,
+};
+use crate::utilities::FrameTimer;
+use crate::projectile::{Projectile, SpawnFrame};
+use crate::safe_zone::{safe_zone_enabled, SafeZone};
+use crate::settings::{AimPreview, GameSettings};
+
+/// Number of particles in a hit burst
+const HIT_PARTICLE_COUNT: usize = 16;
+/// Number of particles in a hit burst with reduced motion on
+const REDUCED_MOTION_HIT_PARTICLE_COUNT: usize = 4;
+/// Seconds a hit particle lives
+const HIT_PARTICLE_LIFETIME: f32 = 0.5;
+const HIT_PARTICLE_SIZE: f32 = 0.2;
+const HIT_PARTICLE_MIN_SPEED: f32 = 2.0;
+const HIT_PARTICLE_MAX_SPEED: f32 = 6.0;
+
+/// Number of particles in a grenade explosion
+const EXPLOSION_PARTICLE_COUNT: usize = 24;
+const EXPLOSION_COLOR: Color = Color::srgb(1.0, 0.6, 0.1);
+
+/// Seconds a projectile trail ghost takes to fade
+const TRAIL_LIFETIME: f32 = 0.15;
+const TRAIL_SIZE: f32 = 0.12;
+const TRAIL_ALPHA: f32 = 0.6;
+
+/// Color burning players flicker towards
+const BURN_TINT: Srgba = Srgba::new(1.0, 0.45, 0.0, 1.0);
+/// Flickers per second of the burn tint
+const BURN_FLICKER_RATE: f32 = 6.0;
+/// How far towards the burn tint burning players are drawn without flickering
+const STEADY_BURN_TINT_AMOUNT: f32 = 0.5;
+
+/// Length of the aim line drawn past the edge of each player
+const AIM_INDICATOR_LENGTH: f32 = 2.5;
+const AIM_INDICATOR_ALPHA: f32 = 0.25;
+const LOCAL_AIM_INDICATOR_ALPHA: f32 = 0.6;
+/// Color of the line of fire preview and the mark where it stops
+const AIM_PREVIEW_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.35);
+const AIM_PREVIEW_MARK_RADIUS: f32 = 0.15;
+const PLACEMENT_SELECTOR_COLOR: Color = Color::srgba(1.0, 1.0, 0.4, 0.8);
+const SAFE_ZONE_COLOR: Color = Color::srgba(1.0, 0.2, 0.2, 0.8);
+/// Seconds a melee swing stays drawn after it starts
+const MELEE_SWING_SECONDS: f32 = 0.15;
+/// Line segments the swing arc is drawn with
+const MELEE_SWING_SEGMENTS: usize = 12;
+/// Opacity of projectiles fired on a frame that hasn't been confirmed yet
+const PREDICTED_PROJECTILE_ALPHA: f32 = 0.5;
+/// Seconds before crumbling that decaying barriers start to fade out
+const BARRIER_FADE_SECONDS: f32 = 2.0;
+
+/// Registers the purely cosmetic effects to the app. Nothing here is rolled
+/// back, and randomness comes from the thread RNG rather than the match seed.
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            (
+                spawn_hit_particles,
+                spawn_explosion_particles,
+                spawn_projectile_trails.run_if(|settings: Res<GameSettings>| !settings.reduced_motion),
+                update_particles,
+            )
+                .chain(),
+            draw_aim_indicators.after(advance_interpolation),
+            draw_melee_swings.after(advance_interpolation),
+            draw_placement_selector.run_if(building_enabled),
+            draw_safe_zone.run_if(safe_zone_enabled),
+            fade_decaying_barriers,
+            fade_predicted_projectiles,
+            tint_burning_players,
+            draw_aim_preview
+                .after(advance_interpolation)
+                .run_if(|settings: Res<GameSettings>| settings.aim_preview != AimPreview::Off),
+        )
+            .run_if(in_state(AppState::InGame)),
+    )
+    .add_systems(OnExit(AppState::InGame), despawn_particles);
+}
+
+/// A short-lived sprite that drifts and fades out
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    lifetime: Timer,
+    /// Alpha at spawn, faded to zero over the lifetime
+    start_alpha: f32,
+}
+
+/// Bursts particles in the victim's color where they were hit
+fn spawn_hit_particles(
+    mut commands: Commands,
+    mut kills: EventReader<PlayerKilled>,
+    settings: Res<GameSettings>,
+) {
+    let mut rng = rand::thread_rng();
+    let particle_count = if settings.reduced_motion {
+        REDUCED_MOTION_HIT_PARTICLE_COUNT
+    } else {
+        HIT_PARTICLE_COUNT
+    };
+
+    for kill in kills.read() {
+        for i in 0..particle_count {
+            // Spread evenly around the circle, jittered so bursts don't look identical
+            let angle = (i as f32 + rng.gen_range(0.0..1.0)) / particle_count as f32
+                * std::f32::consts::TAU;
+            let speed = rng.gen_range(HIT_PARTICLE_MIN_SPEED..HIT_PARTICLE_MAX_SPEED);
+
+            commands.spawn((
+                Particle {
+                    velocity: Vec2::from_angle(angle) * speed,
+                    lifetime: Timer::from_seconds(HIT_PARTICLE_LIFETIME, TimerMode::Once),
+                    start_alpha: 1.0,
+                },
+                SpriteBundle {
+                    transform: Transform::from_translation(kill.position.extend(300.0)),
+                    sprite: Sprite {
+                        color: kill.victim_color,
+                        custom_size: Some(Vec2::splat(HIT_PARTICLE_SIZE)),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// Bursts particles out to the edge of a grenade's blast
+fn spawn_explosion_particles(
+    mut commands: Commands,
+    mut explosions: EventReader<GrenadeExploded>,
+    settings: Res<GameSettings>,
+) {
+    let mut rng = rand::thread_rng();
+    let particle_count = if settings.reduced_motion {
+        REDUCED_MOTION_HIT_PARTICLE_COUNT
+    } else {
+        EXPLOSION_PARTICLE_COUNT
+    };
+
+    for explosion in explosions.read() {
+        for i in 0..particle_count {
+            let angle = (i as f32 + rng.gen_range(0.0..1.0)) / particle_count as f32
+                * std::f32::consts::TAU;
+            // Fast enough to reach the edge of the blast as the particle fades
+            let speed = GRENADE_BLAST_RADIUS / HIT_PARTICLE_LIFETIME * rng.gen_range(0.6..1.0);
+
+            commands.spawn((
+                Particle {
+                    velocity: Vec2::from_angle(angle) * speed,
+                    lifetime: Timer::from_seconds(HIT_PARTICLE_LIFETIME, TimerMode::Once),
+                    start_alpha: 1.0,
+                },
+                SpriteBundle {
+                    transform: Transform::from_translation(explosion.position.extend(300.0)),
+                    sprite: Sprite {
+                        color: EXPLOSION_COLOR,
+                        custom_size: Some(Vec2::splat(HIT_PARTICLE_SIZE)),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// Leaves a fading ghost behind every projectile each frame, in its owner's color
+fn spawn_projectile_trails(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    projectiles: Query<(&Projectile, &Transform, &Visibility)>,
+) {
+    for (projectile, transform, visibility) in &projectiles {
+        // Don't reveal projectiles hidden by the fog of war
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        commands.spawn((
+            Particle {
+                velocity: Vec2::ZERO,
+                lifetime: Timer::from_seconds(TRAIL_LIFETIME, TimerMode::Once),
+                start_alpha: TRAIL_ALPHA,
+            },
+            SpriteBundle {
+                transform: Transform::from_translation(transform.translation.xy().extend(190.0)),
+                sprite: Sprite {
+                    color: player_color(projectile.owner, &settings.player_colors)
+                        .with_alpha(TRAIL_ALPHA),
+                    custom_size: Some(Vec2::splat(TRAIL_SIZE)),
+                    ..default()
+                },
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Moves particles, fades them out and despawns them when their time is up
+fn update_particles(
+    mut commands: Commands,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+    time: Res<Time>,
+) {
+    for (entity, mut particle, mut transform, mut sprite) in &mut particles {
+        if particle.lifetime.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        transform.translation += (particle.velocity * time.delta_seconds()).extend(0.0);
+        sprite
+            .color
+            .set_alpha(particle.start_alpha * (1.0 - particle.lifetime.fraction()));
+    }
+}
+
+fn despawn_particles(mut commands: Commands, particles: Query<Entity, With<Particle>>) {
+    for entity in &particles {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Draws a faint line from each player along the direction they would fire
+fn draw_aim_indicators(
+    mut gizmos: Gizmos,
+    humans: HumanPlayers,
+    players: Query<(&Player, &Transform, &MovementDirection, &Visibility, Option<&Interpolated>)>,
+    settings: Res<GameSettings>,
+    fraction: Res<InterpolationFraction>,
+) {
+    for (player, transform, aim, visibility, interpolated) in &players {
+        // Players hidden by the fog of war shouldn't give away their aim
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let alpha = if humans.contains(player.handle) {
+            LOCAL_AIM_INDICATOR_ALPHA
+        } else {
+            AIM_INDICATOR_ALPHA
+        };
+
+        let start = drawn_position(transform, interpolated, &fraction) + aim.0 * settings.player_radius;
+        let end = start + aim.0 * AIM_INDICATOR_LENGTH;
+        gizmos.line_2d(start, end, player.color.with_alpha(alpha));
+    }
+}
+
+/// Sweeps an arc in front of players who just swung a melee attack
+///
+/// Read straight off the rolled back cooldown, so the swing shows up on the
+/// frame it's predicted rather than once it's confirmed.
+fn draw_melee_swings(
+    mut gizmos: Gizmos,
+    players: Query<(&Player, &Transform, &MovementDirection, &MeleeCooldown, &Visibility, Option<&Interpolated>)>,
+    settings: Res<GameSettings>,
+    fraction: Res<InterpolationFraction>,
+) {
+    let cooldown_frames = FrameTimer::from_seconds(MELEE_COOLDOWN_SECONDS, settings.tick_rate).frames_remaining;
+    let swing_frames = FrameTimer::from_seconds(MELEE_SWING_SECONDS, settings.tick_rate).frames_remaining;
+
+    for (player, transform, aim, cooldown, visibility, interpolated) in &players {
+        let frames_since_swing = cooldown_frames.saturating_sub(cooldown.0.frames_remaining);
+        if cooldown.0.finished() || frames_since_swing >= swing_frames || *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let center = drawn_position(transform, interpolated, &fraction);
+        let radius = settings.player_radius + MELEE_RANGE;
+        let half_arc = MELEE_HALF_ARC_DEGREES.to_radians();
+        let points = (0..=MELEE_SWING_SEGMENTS).map(|segment| {
+            let angle = -half_arc + 2.0 * half_arc * segment as f32 / MELEE_SWING_SEGMENTS as f32;
+            center + Vec2::from_angle(angle).rotate(aim.0) * radius
+        });
+        gizmos.linestrip_2d(points, player.color);
+    }
+}
+
+/// Where a player is drawn this frame, so lines start from the sprite rather than the simulation position
+fn drawn_position(transform: &Transform, interpolated: Option<&Interpolated>, fraction: &InterpolationFraction) -> Vec2 {
+    interpolated.map_or(transform.translation.xy(), |interpolated| interpolated.position(fraction))
+}
+
+/// Distance along `direction` from a point inside the map to the map edge
+fn distance_to_map_edge(start: Vec2, direction: Vec2) -> f32 {
+    let half_world_size = WORLD_SIZE as f32 * 0.5;
+    let mut distance = f32::INFINITY;
+
+    for axis in 0..2 {
+        if direction[axis].abs() > f32::EPSILON {
+            let edge = half_world_size * direction[axis].signum();
+            distance = distance.min((edge - start[axis]) / direction[axis]);
+        }
+    }
+
+    distance.max(0.0)
+}
+
+/// Draws the local player's line of fire up to the first barrier or the map edge
+fn draw_aim_preview(
+    mut gizmos: Gizmos,
+    settings: Res<GameSettings>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    humans: HumanPlayers,
+    players: Query<(&Player, &Transform, &MovementDirection, Option<&Interpolated>)>,
+    barriers: Query<(&Barrier, &Transform, &Sprite)>,
+    fraction: Res<InterpolationFraction>,
+) {
+    if settings.aim_preview == AimPreview::WhileShooting && !keyboard_input.pressed(KeyCode::Space) {
+        return;
+    }
+
+    for (player, transform, aim, interpolated) in &players {
+        if !humans.contains(player.handle) {
+            continue;
+        }
+
+        let start = drawn_position(transform, interpolated, &fraction) + aim.0 * settings.player_radius;
+
+        // Projectiles are removed once they leave the map, so that's as far as the line goes
+        let edge_distance = distance_to_map_edge(start, aim.0);
+
+        let hit_distance = barriers
+            .iter()
+            .filter(|(barrier, _, _)| barrier.is_solid())
+            .filter_map(|(_, barrier_transform, barrier_sprite)| {
+                let half_size = barrier_sprite.custom_size.unwrap_or(Vec2::ONE) / 2.;
+                ray_box_entry(start, aim.0, barrier_transform.translation.xy(), half_size)
+            })
+            .fold(edge_distance, f32::min);
+
+        let end = start + aim.0 * hit_distance;
+        gizmos.line_2d(start, end, AIM_PREVIEW_COLOR);
+        gizmos.circle_2d(end, AIM_PREVIEW_MARK_RADIUS, AIM_PREVIEW_COLOR);
+    }
+}
+
+/// Outlines the cell picked with the keyboard or gamepad placement selector
+fn draw_placement_selector(mut gizmos: Gizmos, selector: Res<PlacementSelector>) {
+    let Some(cell) = selector.cell else {
+        return;
+    };
+
+    let center = cell.as_vec2() + Vec2::splat(0.5) - Vec2::splat(WORLD_SIZE as f32 / 2.);
+    gizmos.rect_2d(center, 0.0, Vec2::ONE, PLACEMENT_SELECTOR_COLOR);
+}
+
+/// Outlines the edge of the shrinking safe zone
+fn draw_safe_zone(mut gizmos: Gizmos, zone: Res<SafeZone>) {
+    gizmos.circle_2d(zone.center, zone.radius, SAFE_ZONE_COLOR).resolution(128);
+}
+
+/// Draws projectiles that were only fired on a predicted frame see-through, so
+/// shots that a rollback might take back stand out from confirmed ones
+fn fade_predicted_projectiles(
+    mut projectiles: Query<(&SpawnFrame, &mut Sprite), With<Projectile>>,
+    confirmed_frame: Option<Res<ConfirmedFrameCount>>,
+) {
+    let Some(confirmed_frame) = confirmed_frame else {
+        return;
+    };
+
+    for (spawn_frame, mut sprite) in &mut projectiles {
+        let alpha = if spawn_frame.0 > confirmed_frame.0 {
+            PREDICTED_PROJECTILE_ALPHA
+        } else {
+            1.0
+        };
+        if sprite.color.alpha() != alpha {
+            sprite.color.set_alpha(alpha);
+        }
+    }
+}
+
+/// Fades barriers out over their last moments before they crumble
+fn fade_decaying_barriers(
+    mut barriers: Query<(&Barrier, &BarrierDecay, &mut Sprite), Changed<BarrierDecay>>,
+    settings: Res<GameSettings>,
+) {
+    let fade_frames = BARRIER_FADE_SECONDS * settings.tick_rate as f32;
+    for (barrier, decay, mut sprite) in &mut barriers {
+        let remaining = (decay.frames_remaining as f32 / fade_frames).min(1.0);
+        sprite.color.set_alpha(barrier.alpha() * remaining);
+    }
+}
+
+/// Flickers burning players towards orange and restores their color once the fire is out
+///
+/// With reduced motion on the tint is held steady instead.
+fn tint_burning_players(
+    mut players: Query<(&Player, &mut Sprite, Option<&Burning>)>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+) {
+    for (player, mut sprite, burning) in &mut players {
+        sprite.color = if burning.is_some() {
+            let amount = if settings.reduced_motion {
+                STEADY_BURN_TINT_AMOUNT
+            } else {
+                let flicker = (time.elapsed_seconds() * BURN_FLICKER_RATE * std::f32::consts::TAU).sin();
+                0.4 + 0.3 * flicker
+            };
+            player.color.to_srgba().mix(&BURN_TINT, amount).into()
+        } else {
+            player.color
+        };
+    }
+}
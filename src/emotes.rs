@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+use bevy_ggrs::{GgrsSchedule, PlayerInputs, RollbackFrameCount};
+use crate::{AppState, GameConfig, GamePhase};
+use crate::events::{EmoteSent, PendingEvents};
+use crate::input_handler::get_emote;
+use crate::network_manager::NUM_PLAYERS;
+use crate::player_module::Player;
+use crate::settings::GameSettings;
+use crate::utilities::FrameTimer;
+
+/// Text of each quick-chat emote, sent with the number keys
+const EMOTES: [&str; 4] = ["GG", "Nice shot!", "Oops", "Thanks!"];
+/// Seconds before a player can send another emote, so holding a key doesn't spam
+const EMOTE_COOLDOWN_SECONDS: f32 = 2.0;
+/// Seconds an emote bubble stays above the player
+const EMOTE_BUBBLE_SECONDS: f32 = 2.0;
+/// Text is laid out in pixels, so bubbles are scaled down to world units
+const EMOTE_BUBBLE_SCALE: f32 = 0.02;
+
+/// Frames until each player can emote again, by handle
+#[derive(Resource, Clone)]
+pub struct EmoteCooldowns(Vec<FrameTimer>);
+
+impl Default for EmoteCooldowns {
+    fn default() -> Self {
+        EmoteCooldowns(vec![FrameTimer::default(); NUM_PLAYERS])
+    }
+}
+
+/// Speech bubble above a player, removed when its timer runs out
+#[derive(Component, Deref, DerefMut)]
+struct EmoteBubble(Timer);
+
+/// Registers the quick-chat emotes to the app
+///
+/// Emotes travel in the input word rather than over the chat channel, so they
+/// reach spectators too, and go through the rollback simulation only to be
+/// rate limited and turned into confirmed `EmoteSent` events.
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<EmoteCooldowns>()
+        .add_systems(OnEnter(AppState::Connecting), reset_emote_cooldowns)
+        .add_systems(
+            GgrsSchedule,
+            send_emotes.after(bevy_roll_safe::apply_state_transition::<GamePhase>),
+        )
+        .add_systems(
+            Update,
+            (show_emote_bubbles, expire_emote_bubbles).run_if(in_state(AppState::InGame)),
+        );
+}
+
+/// Text of an emote, if the index is a known emote
+pub fn emote_text(emote: u8) -> Option<&'static str> {
+    EMOTES.get(emote as usize).copied()
+}
+
+fn reset_emote_cooldowns(mut cooldowns: ResMut<EmoteCooldowns>) {
+    *cooldowns = EmoteCooldowns::default();
+}
+
+/// Raises an event for every player holding an emote key who is off cooldown
+fn send_emotes(
+    inputs: Res<PlayerInputs<GameConfig>>,
+    mut cooldowns: ResMut<EmoteCooldowns>,
+    mut pending_events: ResMut<PendingEvents>,
+    frame: Res<RollbackFrameCount>,
+    settings: Res<GameSettings>,
+) {
+    for (player, cooldown) in cooldowns.0.iter_mut().enumerate() {
+        cooldown.tick();
+
+        let (input, _) = inputs[player];
+        let Some(emote) = get_emote(input) else {
+            continue;
+        };
+        if !cooldown.finished() {
+            continue;
+        }
+
+        *cooldown = FrameTimer::from_seconds(EMOTE_COOLDOWN_SECONDS, settings.tick_rate);
+        pending_events.push(&frame, EmoteSent { player, emote });
+    }
+}
+
+/// Pops a bubble with the emote's text above the player who sent it
+fn show_emote_bubbles(
+    mut commands: Commands,
+    mut emotes: EventReader<EmoteSent>,
+    players: Query<(Entity, &Player, Option<&Children>)>,
+    existing_bubbles: Query<(), With<EmoteBubble>>,
+) {
+    for emote in emotes.read() {
+        let Some(text) = emote_text(emote.emote) else {
+            continue;
+        };
+        let Some((player_entity, _, children)) = players
+            .iter()
+            .find(|(_, player, _)| player.handle == emote.player)
+        else {
+            continue;
+        };
+
+        // A new emote replaces the player's previous bubble
+        for &child in children.into_iter().flatten() {
+            if existing_bubbles.contains(child) {
+                commands.entity(child).despawn_recursive();
+            }
+        }
+
+        let bubble = commands
+            .spawn((
+                EmoteBubble(Timer::from_seconds(EMOTE_BUBBLE_SECONDS, TimerMode::Once)),
+                Text2dBundle {
+                    text: Text::from_section(
+                        text,
+                        TextStyle {
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    transform: Transform::from_xyz(0.0, 1.0, 50.0)
+                        .with_scale(Vec3::splat(EMOTE_BUBBLE_SCALE)),
+                    ..default()
+                },
+            ))
+            .id();
+        commands.entity(player_entity).add_child(bubble);
+    }
+}
+
+fn expire_emote_bubbles(
+    mut commands: Commands,
+    mut bubbles: Query<(Entity, &mut EmoteBubble)>,
+    time: Res<Time>,
+) {
+    for (entity, mut timer) in &mut bubbles {
+        if timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use bevy::prelude::*;
+use bevy_ggrs::{ConfirmedFrameCount, GgrsSchedule, RollbackFrameCount};
+use crate::{AppState, GamePhase};
+
+// Gameplay events are raised inside the rollback schedule, which may simulate
+// the same frame several times while predicting and correcting. Sending Bevy
+// events directly from there would duplicate them (or announce kills that a
+// rollback later undoes), so rollback systems instead record them in
+// `PendingEvents` tagged with the frame they happened on:
+//
+// 1. Before each simulated frame, anything recorded for that frame or later is
+//    thrown away, since it came from a prediction that is being re-simulated.
+// 2. Every render frame, entries at or before the confirmed frame can no
+//    longer change and are forwarded as ordinary Bevy events for `Update`
+//    systems (UI, particles, audio) to consume exactly once.
+
+/// What dealt the damage that killed a player
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KillCause {
+    Shot,
+    Melee,
+    Grenade,
+    Burn,
+    /// Caught outside the safe zone, which counts as a self-inflicted death
+    SafeZone,
+}
+
+/// A player's health ran out and they were removed from the round
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PlayerKilled {
+    pub killer: usize,
+    pub victim: usize,
+    pub cause: KillCause,
+    /// Where the victim was standing when hit
+    pub position: Vec2,
+    pub victim_color: Color,
+}
+
+/// A player fired a projectile
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ProjectileFired {
+    pub owner: usize,
+}
+
+/// A player placed a barrier
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BarrierPlaced {
+    pub owner: usize,
+}
+
+/// The round finished, with the last player standing if there was one
+#[derive(Event, Clone, Copy, Debug)]
+pub struct RoundEnded {
+    pub winner: Option<usize>,
+}
+
+/// A grenade went off
+#[derive(Event, Clone, Copy, Debug)]
+pub struct GrenadeExploded {
+    pub owner: usize,
+    pub position: Vec2,
+}
+
+/// A player sent a quick-chat emote
+#[derive(Event, Clone, Copy, Debug)]
+pub struct EmoteSent {
+    pub player: usize,
+    /// Index into the emote list, see `emotes::emote_text`
+    pub emote: u8,
+}
+
+/// A player's kill streak reached a length that earns a reward
+#[derive(Event, Clone, Copy, Debug)]
+pub struct KillStreakReached {
+    pub player: usize,
+    /// Kills since the player last died
+    pub streak: u32,
+}
+
+/// Any gameplay event that can be recorded from the rollback schedule
+#[derive(Clone, Copy, Debug)]
+pub enum GameplayEvent {
+    PlayerKilled(PlayerKilled),
+    ProjectileFired(ProjectileFired),
+    BarrierPlaced(BarrierPlaced),
+    RoundEnded(RoundEnded),
+    EmoteSent(EmoteSent),
+    GrenadeExploded(GrenadeExploded),
+    KillStreakReached(KillStreakReached),
+}
+
+impl From<PlayerKilled> for GameplayEvent {
+    fn from(event: PlayerKilled) -> Self {
+        GameplayEvent::PlayerKilled(event)
+    }
+}
+
+impl From<ProjectileFired> for GameplayEvent {
+    fn from(event: ProjectileFired) -> Self {
+        GameplayEvent::ProjectileFired(event)
+    }
+}
+
+impl From<BarrierPlaced> for GameplayEvent {
+    fn from(event: BarrierPlaced) -> Self {
+        GameplayEvent::BarrierPlaced(event)
+    }
+}
+
+impl From<RoundEnded> for GameplayEvent {
+    fn from(event: RoundEnded) -> Self {
+        GameplayEvent::RoundEnded(event)
+    }
+}
+
+impl From<EmoteSent> for GameplayEvent {
+    fn from(event: EmoteSent) -> Self {
+        GameplayEvent::EmoteSent(event)
+    }
+}
+
+impl From<GrenadeExploded> for GameplayEvent {
+    fn from(event: GrenadeExploded) -> Self {
+        GameplayEvent::GrenadeExploded(event)
+    }
+}
+
+impl From<KillStreakReached> for GameplayEvent {
+    fn from(event: KillStreakReached) -> Self {
+        GameplayEvent::KillStreakReached(event)
+    }
+}
+
+/// Gameplay events waiting for their frame to be confirmed. Deliberately not
+/// registered for rollback, see the module comment.
+#[derive(Resource, Default)]
+pub struct PendingEvents {
+    events: Vec<(i32, GameplayEvent)>,
+}
+
+impl PendingEvents {
+    /// Records an event raised while simulating `frame`
+    pub fn push(&mut self, frame: &RollbackFrameCount, event: impl Into<GameplayEvent>) {
+        self.events.push((frame.0, event.into()));
+    }
+}
+
+/// Registers the gameplay events to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<PendingEvents>()
+        .add_event::<PlayerKilled>()
+        .add_event::<ProjectileFired>()
+        .add_event::<BarrierPlaced>()
+        .add_event::<RoundEnded>()
+        .add_event::<EmoteSent>()
+        .add_event::<GrenadeExploded>()
+        .add_event::<KillStreakReached>()
+        .add_systems(
+            GgrsSchedule,
+            discard_mispredicted_events
+                .before(bevy_roll_safe::apply_state_transition::<GamePhase>),
+        )
+        .add_systems(
+            Update,
+            deliver_confirmed_events.run_if(in_state(AppState::InGame)),
+        )
+        .add_systems(OnExit(AppState::InGame), clear_pending_events);
+}
+
+/// Drops events from an earlier simulation of the frame about to be re-run
+fn discard_mispredicted_events(
+    mut pending: ResMut<PendingEvents>,
+    frame: Res<RollbackFrameCount>,
+) {
+    pending.events.retain(|(event_frame, _)| *event_frame < frame.0);
+}
+
+/// Sends every event whose frame can no longer be rolled back
+fn deliver_confirmed_events(
+    mut pending: ResMut<PendingEvents>,
+    confirmed_frame: Res<ConfirmedFrameCount>,
+    mut player_killed: EventWriter<PlayerKilled>,
+    mut projectile_fired: EventWriter<ProjectileFired>,
+    mut barrier_placed: EventWriter<BarrierPlaced>,
+    mut round_ended: EventWriter<RoundEnded>,
+    mut emote_sent: EventWriter<EmoteSent>,
+    mut grenade_exploded: EventWriter<GrenadeExploded>,
+    mut kill_streak_reached: EventWriter<KillStreakReached>,
+) {
+    let confirmed_frame = confirmed_frame.0;
+    pending.events.retain(|&(event_frame, event)| {
+        if event_frame > confirmed_frame {
+            return true;
+        }
+
+        match event {
+            GameplayEvent::PlayerKilled(event) => {
+                player_killed.send(event);
+            }
+            GameplayEvent::ProjectileFired(event) => {
+                projectile_fired.send(event);
+            }
+            GameplayEvent::BarrierPlaced(event) => {
+                barrier_placed.send(event);
+            }
+            GameplayEvent::RoundEnded(event) => {
+                round_ended.send(event);
+            }
+            GameplayEvent::EmoteSent(event) => {
+                emote_sent.send(event);
+            }
+            GameplayEvent::GrenadeExploded(event) => {
+                grenade_exploded.send(event);
+            }
+            GameplayEvent::KillStreakReached(event) => {
+                kill_streak_reached.send(event);
+            }
+        }
+        false
+    });
+}
+
+fn clear_pending_events(mut pending: ResMut<PendingEvents>) {
+    pending.events.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+
+    fn event_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<PendingEvents>();
+        world.init_resource::<ConfirmedFrameCount>();
+        world.init_resource::<Events<PlayerKilled>>();
+        world.init_resource::<Events<ProjectileFired>>();
+        world.init_resource::<Events<BarrierPlaced>>();
+        world.init_resource::<Events<RoundEnded>>();
+        world.init_resource::<Events<EmoteSent>>();
+        world.init_resource::<Events<GrenadeExploded>>();
+        world.init_resource::<Events<KillStreakReached>>();
+        world
+    }
+
+    fn push(world: &mut World, frame: i32, owner: usize) {
+        world
+            .resource_mut::<PendingEvents>()
+            .push(&RollbackFrameCount(frame), BarrierPlaced { owner });
+    }
+
+    #[test]
+    fn confirmed_events_are_delivered_exactly_once() {
+        let mut world = event_world();
+        // The confirmed frame starts at zero
+        push(&mut world, 0, 0);
+        push(&mut world, 1, 1);
+
+        world.run_system_once(deliver_confirmed_events);
+        world.run_system_once(deliver_confirmed_events);
+
+        let delivered: Vec<usize> = world
+            .resource_mut::<Events<BarrierPlaced>>()
+            .drain()
+            .map(|event| event.owner)
+            .collect();
+        assert_eq!(delivered, vec![0]);
+        // The unconfirmed event waits for its frame
+        assert_eq!(world.resource::<PendingEvents>().events.len(), 1);
+    }
+
+    #[test]
+    fn resimulated_frames_drop_their_earlier_events() {
+        let mut world = event_world();
+        push(&mut world, 3, 0);
+        push(&mut world, 4, 0);
+        push(&mut world, 5, 1);
+
+        world.insert_resource(RollbackFrameCount(4));
+        world.run_system_once(discard_mispredicted_events);
+
+        let frames: Vec<i32> = world
+            .resource::<PendingEvents>()
+            .events
+            .iter()
+            .map(|&(frame, _)| frame)
+            .collect();
+        assert_eq!(frames, vec![3]);
+    }
+}
@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use crate::AppState;
+use crate::barriers::{ray_box_entry, Barrier};
+use crate::bots::HumanPlayers;
+use crate::player_module::Player;
+use crate::projectile::Projectile;
+use crate::settings::GameSettings;
+
+/// How far the local player can see in world units
+const VISION_RADIUS: f32 = 10.0;
+
+/// Registers the fog of war to the app. It only changes what is drawn
+/// locally; every peer still simulates the whole match.
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        update_fog_of_war
+            .run_if(in_state(AppState::InGame))
+            .run_if(|settings: Res<GameSettings>| settings.fog_of_war),
+    );
+}
+
+/// Hides opponents and projectiles that are out of range or behind a barrier
+fn update_fog_of_war(
+    humans: HumanPlayers,
+    mut players: Query<(&Player, &Transform, &mut Visibility), Without<Projectile>>,
+    mut projectiles: Query<(&Transform, &mut Visibility), (With<Projectile>, Without<Player>)>,
+    barriers: Query<(&Barrier, &Transform, &Sprite)>,
+) {
+    let viewer = players
+        .iter()
+        .find(|(player, _, _)| humans.contains(player.handle))
+        .map(|(_, transform, _)| transform.translation.xy());
+
+    // Spectators and dead players see everything
+    let Some(viewer) = viewer else {
+        for (_, _, mut visibility) in &mut players {
+            *visibility = Visibility::Inherited;
+        }
+        for (_, mut visibility) in &mut projectiles {
+            *visibility = Visibility::Inherited;
+        }
+        return;
+    };
+
+    let blockers: Vec<(Vec2, Vec2)> = barriers
+        .iter()
+        .filter(|(barrier, _, _)| barrier.is_solid())
+        .map(|(_, transform, sprite)| {
+            let size = sprite.custom_size.unwrap_or(Vec2::ONE);
+            (transform.translation.xy(), size / 2.)
+        })
+        .collect();
+
+    let can_see = |target: Vec2| {
+        viewer.distance(target) <= VISION_RADIUS
+            && !blockers
+                .iter()
+                .any(|&(center, half_size)| {
+                    // Only boxes between the viewer and the target block sight
+                    ray_box_entry(viewer, target - viewer, center, half_size)
+                        .is_some_and(|t| t <= 1.0)
+                })
+    };
+
+    for (player, transform, mut visibility) in &mut players {
+        if humans.contains(player.handle) {
+            continue;
+        }
+        *visibility = if can_see(transform.translation.xy()) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    for (transform, mut visibility) in &mut projectiles {
+        *visibility = if can_see(transform.translation.xy()) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
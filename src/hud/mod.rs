@@ -0,0 +1,417 @@
+use bevy::prelude::*;
+use crate::{AppState, GamePhase};
+use crate::bots::HumanPlayers;
+use crate::events::{KillCause, KillStreakReached, PlayerKilled, RoundEnded};
+use crate::input_handler::LocalReady;
+use crate::network_manager::NUM_PLAYERS;
+use crate::player_module::Player;
+use crate::projectile::Ammo;
+use crate::settings::{GameMode, GameSettings};
+use crate::utilities::{MatchProgress, PlayerLives, PlayerScores, ReadyPlayers};
+
+/// How many kills the feed shows at once
+const KILL_FEED_LENGTH: usize = 5;
+/// Seconds a kill stays in the feed
+const KILL_FEED_ENTRY_SECONDS: f32 = 5.0;
+
+/// Registers the in-game HUD to the app
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(AppState::InGame),
+        (spawn_kill_feed, spawn_warmup_status, spawn_round_indicator, spawn_ammo_counter),
+    )
+        .add_systems(OnExit(AppState::InGame), despawn_hud)
+        .add_systems(
+            Update,
+            (
+                (add_kill_feed_entries, expire_kill_feed_entries).chain(),
+                update_warmup_status,
+                update_round_indicator,
+                update_ammo_counter,
+                (show_round_banner, clear_round_banner).chain(),
+            )
+                .run_if(in_state(AppState::InGame)),
+        );
+}
+
+/// Marker for every HUD entity
+#[derive(Component)]
+struct Hud;
+
+/// Container the kill feed lines are added to
+#[derive(Component)]
+struct KillFeed;
+
+/// Centered text telling players how to ready up during warmup
+#[derive(Component)]
+struct WarmupStatus;
+
+/// Text at the top of the screen saying which round is being played
+#[derive(Component)]
+struct RoundIndicator;
+
+/// Text in the bottom right corner showing the local player's ammo
+#[derive(Component)]
+struct AmmoCounter;
+
+/// Centered banner announcing the result of the round that just ended
+#[derive(Component)]
+struct RoundBanner;
+
+/// One line of the kill feed, removed when its timer runs out
+#[derive(Component, Deref, DerefMut)]
+struct KillFeedEntry(Timer);
+
+fn spawn_kill_feed(mut commands: Commands) {
+    commands.spawn((
+        Hud,
+        KillFeed,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(12.0),
+                right: Val::Px(12.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexEnd,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            ..default()
+        },
+    ));
+}
+
+fn spawn_warmup_status(mut commands: Commands) {
+    commands
+        .spawn((
+            Hud,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Percent(20.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                WarmupStatus,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+/// Shows how many players are ready while in warmup and hides the text afterwards
+fn update_warmup_status(
+    phase: Res<State<GamePhase>>,
+    ready_players: Res<ReadyPlayers>,
+    local_ready: Res<LocalReady>,
+    humans: HumanPlayers,
+    mut status: Query<(&mut Text, &mut Visibility), With<WarmupStatus>>,
+) {
+    for (mut text, mut visibility) in &mut status {
+        if *phase.get() != GamePhase::Warmup {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        *visibility = Visibility::Inherited;
+        let ready_count = ready_players.ready_count();
+        text.sections[0].value = if local_ready.0 || humans.is_empty() {
+            format!("Waiting for players to ready ({ready_count}/{NUM_PLAYERS})")
+        } else {
+            format!("Press R to ready up ({ready_count}/{NUM_PLAYERS} ready)")
+        };
+    }
+}
+
+fn spawn_round_indicator(mut commands: Commands) {
+    commands
+        .spawn((
+            Hud,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Px(12.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                RoundIndicator,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+fn update_round_indicator(
+    phase: Res<State<GamePhase>>,
+    progress: Res<MatchProgress>,
+    lives: Res<PlayerLives>,
+    player_scores: Res<PlayerScores>,
+    settings: Res<GameSettings>,
+    mut indicator: Query<&mut Text, With<RoundIndicator>>,
+) {
+    // While a round is being played it hasn't been counted yet
+    let round = match phase.get() {
+        GamePhase::ActiveRound => progress.rounds_played + 1,
+        _ => progress.rounds_played.max(1),
+    };
+
+    let label = match settings.game_mode {
+        _ if progress.sudden_death => String::from("Sudden death - first kill wins"),
+        GameMode::ScoreLimit => format!("Round {round} - first to {}", settings.winning_score),
+        GameMode::BestOf => format!("Round {round} of {}", settings.max_rounds),
+        GameMode::Elimination => {
+            let remaining: Vec<String> = (0..NUM_PLAYERS)
+                .map(|player| match lives.get(player) {
+                    0 => format!("Player {} out", player + 1),
+                    1 => format!("Player {} 1 life", player + 1),
+                    count => format!("Player {} {count} lives", player + 1),
+                })
+                .collect();
+            format!("Round {round} - last one standing\n{}", remaining.join("  |  "))
+        }
+        GameMode::Demolition => {
+            let destroyed: Vec<String> = (0..NUM_PLAYERS)
+                .map(|player| format!("Player {} {}", player + 1, player_scores.get(player)))
+                .collect();
+            format!(
+                "Round {round} - first to destroy {} barriers\n{}",
+                settings.barrier_target,
+                destroyed.join("  |  ")
+            )
+        }
+    };
+
+    for mut text in &mut indicator {
+        if *phase.get() == GamePhase::Warmup {
+            text.sections[0].value.clear();
+        } else {
+            text.sections[0].value.clone_from(&label);
+        }
+    }
+}
+
+fn spawn_ammo_counter(mut commands: Commands) {
+    commands.spawn((
+        Hud,
+        AmmoCounter,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 32.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(12.0),
+            bottom: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+}
+
+/// Shows the rounds left in the local player's magazine and reserve, or that they're reloading
+fn update_ammo_counter(
+    humans: HumanPlayers,
+    players: Query<(&Player, &Ammo)>,
+    mut counter: Query<&mut Text, With<AmmoCounter>>,
+) {
+    let ammo = players
+        .iter()
+        .find(|(player, _)| humans.contains(player.handle))
+        .map(|(_, ammo)| ammo);
+
+    let label = match ammo {
+        None => String::new(),
+        Some(ammo) if ammo.reloading.is_some() => String::from("Reloading..."),
+        Some(ammo) => format!("{} / {}", ammo.in_mag, ammo.reserve),
+    };
+
+    for mut text in &mut counter {
+        text.sections[0].value.clone_from(&label);
+    }
+}
+
+/// Tells the local player whether they won or lost the round
+fn show_round_banner(
+    mut commands: Commands,
+    mut rounds_ended: EventReader<RoundEnded>,
+    humans: HumanPlayers,
+    existing_banners: Query<Entity, With<RoundBanner>>,
+) {
+    let Some(round_ended) = rounds_ended.read().last() else {
+        return;
+    };
+
+    for entity in &existing_banners {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let (text, color) = match round_ended.winner {
+        Some(winner) if humans.contains(winner) => {
+            (String::from("You won the round!"), Color::srgb(0.3, 0.9, 0.3))
+        }
+        // Spectators aren't on either side, so just name the winner
+        Some(winner) if humans.is_empty() => {
+            (format!("Player {} won the round", winner + 1), Color::WHITE)
+        }
+        Some(_) => (String::from("You lost"), Color::srgb(0.9, 0.3, 0.3)),
+        None => (String::from("Draw"), Color::WHITE),
+    };
+
+    commands
+        .spawn((
+            Hud,
+            RoundBanner,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                text,
+                TextStyle {
+                    font_size: 56.0,
+                    color,
+                    ..default()
+                },
+            ));
+        });
+}
+
+/// Removes the banner once the round over phase ends
+fn clear_round_banner(
+    mut commands: Commands,
+    phase: Res<State<GamePhase>>,
+    banners: Query<Entity, With<RoundBanner>>,
+) {
+    if *phase.get() == GamePhase::RoundOver {
+        return;
+    }
+
+    for entity in &banners {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn despawn_hud(mut commands: Commands, hud: Query<Entity, With<Hud>>) {
+    for entity in &hud {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Formats a player handle for display, calling out the local player
+fn player_name(handle: usize, humans: &HumanPlayers) -> String {
+    if humans.contains(handle) {
+        String::from("You")
+    } else {
+        format!("Player {}", handle + 1)
+    }
+}
+
+fn add_kill_feed_entries(
+    mut commands: Commands,
+    mut kills: EventReader<PlayerKilled>,
+    mut streaks: EventReader<KillStreakReached>,
+    humans: HumanPlayers,
+    kill_feed: Query<(Entity, Option<&Children>), With<KillFeed>>,
+) {
+    let Ok((feed, entries)) = kill_feed.get_single() else {
+        return;
+    };
+
+    let kill_lines = kills.read().map(|kill| {
+        let victim = player_name(kill.victim, &humans);
+        if kill.killer == kill.victim {
+            return match kill.cause {
+                KillCause::SafeZone => format!("{victim} was caught outside the safe zone"),
+                KillCause::Grenade => format!("{victim} was caught in their own blast"),
+                KillCause::Shot | KillCause::Melee | KillCause::Burn => format!("{victim} died"),
+            };
+        }
+
+        let killer = player_name(kill.killer, &humans);
+        match kill.cause {
+            KillCause::Shot => format!("{killer} shot {victim}"),
+            KillCause::Melee => format!("{killer} struck down {victim}"),
+            KillCause::Grenade => format!("{killer} blew up {victim}"),
+            KillCause::Burn => format!("{killer} burned {victim}"),
+            KillCause::SafeZone => format!("{victim} was caught outside the safe zone"),
+        }
+    });
+    let streak_lines = streaks.read().map(|streak| {
+        format!("{} is on a {} kill streak!", player_name(streak.player, &humans), streak.streak)
+    });
+    let lines: Vec<String> = kill_lines.chain(streak_lines).collect();
+
+    // Make room by dropping the oldest lines, only keeping the newest if
+    // more arrive at once than the feed holds
+    let existing = entries.map_or(&[][..], |children| &children[..]);
+    let overflow = (existing.len() + lines.len()).saturating_sub(KILL_FEED_LENGTH);
+    for oldest in existing.iter().take(overflow) {
+        commands.entity(*oldest).despawn_recursive();
+    }
+    let skipped = overflow.saturating_sub(existing.len());
+
+    for text in lines.into_iter().skip(skipped) {
+        let entry = commands
+            .spawn((
+                KillFeedEntry(Timer::from_seconds(KILL_FEED_ENTRY_SECONDS, TimerMode::Once)),
+                TextBundle::from_section(
+                    text,
+                    TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ))
+            .id();
+        commands.entity(feed).add_child(entry);
+    }
+}
+
+fn expire_kill_feed_entries(
+    mut commands: Commands,
+    mut entries: Query<(Entity, &mut KillFeedEntry)>,
+    time: Res<Time>,
+) {
+    for (entity, mut timer) in &mut entries {
+        if timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -1,19 +1,105 @@
 use bevy::{prelude::*, utils::HashMap};
-use bevy_ggrs::{LocalInputs, LocalPlayers};
+use bevy_ggrs::LocalInputs;
 use crate::WORLD_SIZE;
 use crate::GameConfig;
+use crate::bots::HumanPlayers;
+use crate::chat::ChatState;
+use crate::player_module::{MovementDirection, Player};
+use crate::settings::GameSettings;
+
+// Layout of the input word shared by every player each frame. Every field is
+// independent, so moving, shooting and clicking can all happen in one frame:
+//
+//   bits 0-3   keyboard movement (up, down, left, right)
+//   bit  4     shoot
+//   bit  5     click (barrier placement at the cursor cell)
+//   bits 6-11  cursor cell x, always present. While the placement selector is
+//              active this is the selector's cell instead of the mouse's.
+//   bits 12-17 cursor cell y, always present, as above
+//   bits 18-23 analog movement angle, quantized to ANALOG_ANGLE_STEPS
+//   bits 24-26 analog movement magnitude, 0 means "use the keyboard bits"
+//   bit  27    ready, held from the moment the player readies up in warmup
+//   bit  28    unused. Loading is confirmed over the lobby channel before the
+//              session starts, see `network_manager::wait_for_players`.
+//   bits 29-31 quick-chat emote held down, 0 means none
+//   bit  32    the click places a slow tile rather than a solid barrier
+//   bit  33    melee attack
+//   bit  34    reload
 
 /// Input flags for player actions
-pub(crate) const INPUT_UP: u32 = 1 << 0;
-pub(crate) const INPUT_DOWN: u32 = 1 << 1;
-pub(crate) const INPUT_LEFT: u32 = 1 << 2;
-pub(crate) const INPUT_RIGHT: u32 = 1 << 3;
-pub(crate) const INPUT_SHOOT: u32 = 1 << 4;
-pub(crate) const INPUT_CLICK: u32 = 1 << 5;
+pub(crate) const INPUT_UP: u64 = 1 << 0;
+pub(crate) const INPUT_DOWN: u64 = 1 << 1;
+pub(crate) const INPUT_LEFT: u64 = 1 << 2;
+pub(crate) const INPUT_RIGHT: u64 = 1 << 3;
+pub(crate) const INPUT_SHOOT: u64 = 1 << 4;
+pub(crate) const INPUT_CLICK: u64 = 1 << 5;
+pub(crate) const INPUT_READY: u64 = 1 << 27;
+pub(crate) const INPUT_SLOW_TILE: u64 = 1 << 32;
+pub(crate) const INPUT_MELEE: u64 = 1 << 33;
+pub(crate) const INPUT_RELOAD: u64 = 1 << 34;
+
+/// Bit offsets and masks for the packed fields
+const CELL_X_SHIFT: u32 = 6;
+const CELL_Y_SHIFT: u32 = 12;
+const CELL_MASK: u64 = 0b11_1111;
+const ANALOG_ANGLE_SHIFT: u32 = 18;
+const ANALOG_ANGLE_MASK: u64 = 0b11_1111;
+const ANALOG_MAGNITUDE_SHIFT: u32 = 24;
+const ANALOG_MAGNITUDE_MASK: u64 = 0b111;
+const EMOTE_SHIFT: u32 = 29;
+const EMOTE_MASK: u64 = 0b111;
+
+/// Number of distinct analog directions that fit in the angle field
+const ANALOG_ANGLE_STEPS: u64 = ANALOG_ANGLE_MASK + 1;
+/// Largest magnitude that fits in the magnitude field
+const ANALOG_MAGNITUDE_STEPS: u64 = ANALOG_MAGNITUDE_MASK;
+
+/// Keys for each quick-chat emote, in the order of `emotes::emote_text`
+const EMOTE_KEYS: [KeyCode; 4] = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+
+/// Stick deflection below which the gamepad is treated as idle
+const GAMEPAD_DEADZONE: f32 = 0.15;
+
+/// Converts a world coordinate to a grid cell index, clamped to the map
+fn world_to_cell(value: f32) -> u32 {
+    (value + WORLD_SIZE as f32 / 2.)
+        .floor()
+        .clamp(0., (WORLD_SIZE - 1) as f32) as u32
+}
 
-/// Converts an `i32` to `u8`, handling overflows
-fn convert_i32_to_u8(value: i32) -> u8 {
-    (value as i8) as u8
+/// Packs a grid cell into the cursor cell fields
+pub(crate) fn encode_cursor_cell(cell_x: u32, cell_y: u32) -> u64 {
+    ((cell_x as u64 & CELL_MASK) << CELL_X_SHIFT) | ((cell_y as u64 & CELL_MASK) << CELL_Y_SHIFT)
+}
+
+/// Quantizes an analog stick vector into the angle and magnitude fields
+pub(crate) fn encode_analog(stick: Vec2) -> u64 {
+    let length = stick.length().min(1.0);
+    if length < GAMEPAD_DEADZONE {
+        return 0;
+    }
+
+    let angle = stick.y.atan2(stick.x).rem_euclid(std::f32::consts::TAU);
+    let angle_step =
+        (angle / std::f32::consts::TAU * ANALOG_ANGLE_STEPS as f32).round() as u64 % ANALOG_ANGLE_STEPS;
+    let magnitude_step = ((length * ANALOG_MAGNITUDE_STEPS as f32).round() as u64).max(1);
+
+    (angle_step << ANALOG_ANGLE_SHIFT) | (magnitude_step << ANALOG_MAGNITUDE_SHIFT)
+}
+
+/// Reads the left stick of the first connected gamepad
+fn read_gamepad_stick(gamepads: &Gamepads, axes: &Axis<GamepadAxis>) -> Vec2 {
+    let Some(gamepad) = gamepads.iter().next() else {
+        return Vec2::ZERO;
+    };
+
+    let x = axes
+        .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+        .unwrap_or(0.0);
+    let y = axes
+        .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+        .unwrap_or(0.0);
+    Vec2::new(x, y)
 }
 
 /// Processes and collects inputs from the player
@@ -21,51 +107,199 @@ pub(crate) fn collect_player_inputs(
     mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
     cursor_pos: Res<MousePosition>,
-    local_players: Res<LocalPlayers>,
+    selector: Res<PlacementSelector>,
+    local_ready: Res<LocalReady>,
+    humans: HumanPlayers,
+    chat: Res<ChatState>,
+    settings: Res<GameSettings>,
 ) {
+    // Keys typed into the chat box don't control the player
+    let key_held = |key: KeyCode| !chat.is_open() && keyboard_input.pressed(key);
     let mut inputs = HashMap::new();
 
-    for handle in &local_players.0 {
-        let mut input_flags = 0u32;
+    // Bots fill in their own inputs afterwards
+    for handle in humans.iter() {
+        let mut input_flags = 0u64;
+
+        let (cell_x, cell_y) = match selector.cell {
+            Some(cell) => (cell.x, cell.y),
+            None => (world_to_cell(cursor_pos.0.x), world_to_cell(cursor_pos.0.y)),
+        };
+        input_flags |= encode_cursor_cell(cell_x, cell_y);
 
         if mouse_input.pressed(MouseButton::Left) {
             input_flags |= INPUT_CLICK;
-            let cursor = cursor_pos.0;
-            let cell_x =
-                convert_i32_to_u8(cursor.x as i32 + WORLD_SIZE as i32 / 2) as u32;
-            let cell_y =
-                convert_i32_to_u8(cursor.y as i32 + WORLD_SIZE as i32 / 2) as u32;
-            input_flags |= cell_x << 6;
-            input_flags |= cell_y << 14;
-        } else {
-            if keyboard_input.pressed(KeyCode::KeyW) {
-                input_flags |= INPUT_UP;
-            }
+        }
 
-            if keyboard_input.pressed(KeyCode::KeyS) {
-                input_flags |= INPUT_DOWN;
-            }
+        let place_pressed = key_held(KeyCode::KeyE)
+            || gamepads.iter().any(|gamepad| {
+                gamepad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::West))
+            });
+        if selector.cell.is_some() && place_pressed {
+            input_flags |= INPUT_CLICK;
+        }
 
-            if keyboard_input.pressed(KeyCode::KeyA) {
-                input_flags |= INPUT_LEFT;
-            }
+        // Slow tiles are placed with the right mouse button, or Q and the
+        // north face button with the placement selector
+        if mouse_input.pressed(MouseButton::Right) {
+            input_flags |= INPUT_CLICK | INPUT_SLOW_TILE;
+        }
 
-            if keyboard_input.pressed(KeyCode::KeyD) {
-                input_flags |= INPUT_RIGHT;
-            }
+        let place_slow_pressed = key_held(KeyCode::KeyQ)
+            || gamepads.iter().any(|gamepad| {
+                gamepad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::North))
+            });
+        if selector.cell.is_some() && place_slow_pressed {
+            input_flags |= INPUT_CLICK | INPUT_SLOW_TILE;
+        }
 
-            if keyboard_input.pressed(KeyCode::Space) {
-                input_flags |= INPUT_SHOOT;
-            }
+        if key_held(KeyCode::KeyW) {
+            input_flags |= INPUT_UP;
         }
 
-        inputs.insert(*handle, input_flags);
+        if key_held(KeyCode::KeyS) {
+            input_flags |= INPUT_DOWN;
+        }
+
+        if key_held(KeyCode::KeyA) {
+            input_flags |= INPUT_LEFT;
+        }
+
+        if key_held(KeyCode::KeyD) {
+            input_flags |= INPUT_RIGHT;
+        }
+
+        if key_held(KeyCode::Space) {
+            input_flags |= INPUT_SHOOT;
+        }
+
+        let melee_pressed = key_held(KeyCode::KeyF)
+            || gamepads.iter().any(|gamepad| {
+                gamepad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::East))
+            });
+        if melee_pressed {
+            input_flags |= INPUT_MELEE;
+        }
+
+        // R also readies up during warmup, when there's nothing to reload
+        let reload_pressed = key_held(KeyCode::KeyR)
+            || gamepads.iter().any(|gamepad| {
+                gamepad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::South))
+            });
+        if reload_pressed {
+            input_flags |= INPUT_RELOAD;
+        }
+
+        input_flags |= encode_analog(read_gamepad_stick(&gamepads, &gamepad_axes));
+
+        if local_ready.0 {
+            input_flags |= INPUT_READY;
+        }
+
+        if let Some(emote) = EMOTE_KEYS.iter().position(|&key| key_held(key)) {
+            input_flags |= (emote as u64 + 1) << EMOTE_SHIFT;
+        }
+
+        // Without building, placement buttons don't send anything
+        if !settings.building_enabled {
+            input_flags &= !(INPUT_CLICK | INPUT_SLOW_TILE);
+        }
+
+        inputs.insert(handle, input_flags);
     }
 
     commands.insert_resource(LocalInputs::<GameConfig>(inputs));
 }
 
+/// Whether the local player has readied up for the current match
+#[derive(Resource, Default)]
+pub struct LocalReady(pub(crate) bool);
+
+/// Readies the local player up when they press R during warmup
+pub fn ready_up(keyboard_input: Res<ButtonInput<KeyCode>>, mut local_ready: ResMut<LocalReady>) {
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        local_ready.0 = true;
+    }
+}
+
+/// Clears the local ready flag before a new match
+pub fn reset_local_ready(mut local_ready: ResMut<LocalReady>) {
+    local_ready.0 = false;
+}
+
+/// Keyboard and gamepad alternative to the mouse for picking where to place a
+/// barrier. Only lives on this peer; the chosen cell reaches the simulation
+/// through the cursor cell bits of the input word.
+#[derive(Resource, Default)]
+pub struct PlacementSelector {
+    /// Selected cell, or `None` while the mouse is being used
+    pub(crate) cell: Option<UVec2>,
+}
+
+/// Moves the placement selector with the arrow keys or D-pad
+///
+/// The selector appears in front of the local player the first time it's
+/// moved, and hands back to the mouse as soon as the mouse moves.
+pub fn move_placement_selector(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut cursor_events: EventReader<CursorMoved>,
+    mut selector: ResMut<PlacementSelector>,
+    humans: HumanPlayers,
+    players: Query<(&Player, &Transform, &MovementDirection)>,
+) {
+    if cursor_events.read().last().is_some() {
+        selector.cell = None;
+    }
+
+    let pressed = |key: KeyCode, button: GamepadButtonType| {
+        keyboard_input.just_pressed(key)
+            || gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, button)))
+    };
+
+    let mut step = IVec2::ZERO;
+    if pressed(KeyCode::ArrowUp, GamepadButtonType::DPadUp) {
+        step.y += 1;
+    }
+    if pressed(KeyCode::ArrowDown, GamepadButtonType::DPadDown) {
+        step.y -= 1;
+    }
+    if pressed(KeyCode::ArrowLeft, GamepadButtonType::DPadLeft) {
+        step.x -= 1;
+    }
+    if pressed(KeyCode::ArrowRight, GamepadButtonType::DPadRight) {
+        step.x += 1;
+    }
+
+    if step == IVec2::ZERO {
+        return;
+    }
+
+    let start = selector.cell.or_else(|| {
+        players
+            .iter()
+            .find(|(player, _, _)| humans.contains(player.handle))
+            .map(|(_, transform, facing)| {
+                let target = transform.translation.xy() + facing.0;
+                UVec2::new(world_to_cell(target.x), world_to_cell(target.y))
+            })
+    });
+    let Some(start) = start else {
+        return;
+    };
+
+    let max_cell = IVec2::splat(WORLD_SIZE as i32 - 1);
+    let moved = (start.as_ivec2() + step).clamp(IVec2::ZERO, max_cell);
+    selector.cell = Some(moved.as_uvec2());
+}
+
 /// Resource to store the current cursor position
 #[derive(Resource)]
 pub struct MousePosition(pub(crate) Vec2);
@@ -94,7 +328,18 @@ pub fn update_mouse_position(
 }
 
 /// Calculates the movement direction from input flags
-pub fn direction(input: u32) -> Vec2 {
+///
+/// Analog input takes priority and may have a length below one. Without it the
+/// keyboard bits are used, producing a unit vector in one of eight directions.
+pub fn direction(input: u64) -> Vec2 {
+    let magnitude_step = (input >> ANALOG_MAGNITUDE_SHIFT) & ANALOG_MAGNITUDE_MASK;
+    if magnitude_step != 0 {
+        let angle_step = (input >> ANALOG_ANGLE_SHIFT) & ANALOG_ANGLE_MASK;
+        let angle = angle_step as f32 / ANALOG_ANGLE_STEPS as f32 * std::f32::consts::TAU;
+        let magnitude = magnitude_step as f32 / ANALOG_MAGNITUDE_STEPS as f32;
+        return Vec2::from_angle(angle) * magnitude;
+    }
+
     let mut direction = Vec2::ZERO;
 
     if input & INPUT_UP != 0 {
@@ -117,17 +362,142 @@ pub fn direction(input: u32) -> Vec2 {
 }
 
 /// Checks if the player is attempting to shoot
-pub fn is_shooting(input: u32) -> bool {
+pub fn is_shooting(input: u64) -> bool {
     input & INPUT_SHOOT != 0
 }
 
+/// Checks if the player is swinging a melee attack
+pub fn is_meleeing(input: u64) -> bool {
+    input & INPUT_MELEE != 0
+}
+
+/// Checks if the player wants to reload their weapon
+pub fn is_reloading(input: u64) -> bool {
+    input & INPUT_RELOAD != 0
+}
+
+/// Checks if the player has readied up
+pub fn is_ready(input: u64) -> bool {
+    input & INPUT_READY != 0
+}
+
+/// Retrieves the emote the player is sending, if any
+pub fn get_emote(input: u64) -> Option<u8> {
+    let emote = (input >> EMOTE_SHIFT) & EMOTE_MASK;
+    emote.checked_sub(1).map(|emote| emote as u8)
+}
+
+/// Retrieves the grid cell under the player's cursor
+pub fn get_cursor_cell(input: u64) -> (u8, u8) {
+    let cell_x = (input >> CELL_X_SHIFT) & CELL_MASK;
+    let cell_y = (input >> CELL_Y_SHIFT) & CELL_MASK;
+    (cell_x as u8, cell_y as u8)
+}
+
+/// Checks if the player's click places a slow tile
+pub fn is_placing_slow_tile(input: u64) -> bool {
+    input & INPUT_SLOW_TILE != 0
+}
+
 /// Retrieves the mouse click position if applicable
-pub fn get_click_position(input: u32) -> Option<(u8, u8)> {
+pub fn get_click_position(input: u64) -> Option<(u8, u8)> {
     if input & INPUT_CLICK != 0 {
-        let cell_x = (input >> 6) & 0b111111;
-        let cell_y = (input >> 14) & 0b111111;
-        Some((cell_x as u8, cell_y as u8))
+        Some(get_cursor_cell(input))
     } else {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_cell_round_trips() {
+        for (x, y) in [(0, 0), (1, 2), (20, 40), (WORLD_SIZE - 1, WORLD_SIZE - 1), (63, 63)] {
+            let input = encode_cursor_cell(x, y);
+            assert_eq!(get_cursor_cell(input), (x as u8, y as u8));
+            assert_eq!(get_click_position(input), None);
+            assert_eq!(get_click_position(input | INPUT_CLICK), Some((x as u8, y as u8)));
+        }
+    }
+
+    #[test]
+    fn cursor_cell_fields_hold_64_cells_per_axis() {
+        // The map's last cell has to fit in the 6-bit cursor fields
+        let last = WORLD_SIZE - 1;
+        assert_eq!(get_cursor_cell(encode_cursor_cell(last, last)), (last as u8, last as u8));
+        // Anything past 63 wraps around instead of spilling into the next field
+        assert_eq!(get_cursor_cell(encode_cursor_cell(64, 65)), (0, 1));
+        assert_eq!(encode_cursor_cell(64, 64), 0);
+    }
+
+    #[test]
+    fn keyboard_directions_are_unit_vectors() {
+        assert_eq!(direction(0), Vec2::ZERO);
+        assert_eq!(direction(INPUT_UP), Vec2::Y);
+        assert_eq!(direction(INPUT_DOWN), Vec2::NEG_Y);
+        assert_eq!(direction(INPUT_LEFT), Vec2::NEG_X);
+        assert_eq!(direction(INPUT_RIGHT), Vec2::X);
+        assert_eq!(direction(INPUT_UP | INPUT_DOWN), Vec2::ZERO);
+        let diagonal = direction(INPUT_UP | INPUT_RIGHT);
+        assert!((diagonal.length() - 1.0).abs() < 1e-6);
+        assert!(diagonal.x > 0.0 && diagonal.x == diagonal.y);
+    }
+
+    #[test]
+    fn analog_direction_round_trips() {
+        for step in 0..ANALOG_ANGLE_STEPS {
+            let angle = step as f32 / ANALOG_ANGLE_STEPS as f32 * std::f32::consts::TAU;
+            let stick = Vec2::from_angle(angle);
+            let decoded = direction(encode_analog(stick));
+            assert!(decoded.distance(stick) < 1e-4, "{stick} decoded as {decoded}");
+        }
+
+        let half = Vec2::new(0.0, 0.5);
+        let decoded = direction(encode_analog(half));
+        assert!(decoded.distance(half) < 1.0 / ANALOG_MAGNITUDE_STEPS as f32);
+    }
+
+    #[test]
+    fn analog_input_overrides_keyboard_bits() {
+        assert_eq!(encode_analog(Vec2::new(GAMEPAD_DEADZONE / 2.0, 0.0)), 0);
+        let decoded = direction(encode_analog(Vec2::X) | INPUT_LEFT);
+        assert!(decoded.distance(Vec2::X) < 1e-4);
+    }
+
+    #[test]
+    fn flags_are_independent() {
+        let everything = INPUT_SHOOT | INPUT_READY | INPUT_SLOW_TILE | INPUT_MELEE | INPUT_RELOAD;
+        assert!(is_shooting(everything) && is_ready(everything) && is_placing_slow_tile(everything));
+        assert!(is_meleeing(everything) && is_reloading(everything));
+        assert!(!is_shooting(everything & !INPUT_SHOOT));
+        assert!(!is_ready(everything & !INPUT_READY));
+        assert!(!is_placing_slow_tile(everything & !INPUT_SLOW_TILE));
+        assert!(!is_meleeing(everything & !INPUT_MELEE));
+        assert!(!is_reloading(everything & !INPUT_RELOAD));
+    }
+
+    #[test]
+    fn emote_round_trips() {
+        assert_eq!(get_emote(0), None);
+        for emote in 0..EMOTE_KEYS.len() as u64 {
+            assert_eq!(get_emote((emote + 1) << EMOTE_SHIFT), Some(emote as u8));
+        }
+    }
+
+    #[test]
+    fn packed_fields_do_not_overlap() {
+        let input = encode_cursor_cell(63, 63)
+            | encode_analog(Vec2::new(-1.0, -0.01))
+            | (EMOTE_MASK << EMOTE_SHIFT)
+            | INPUT_UP
+            | INPUT_CLICK
+            | INPUT_READY;
+        assert_eq!(get_cursor_cell(input), (63, 63));
+        assert_eq!(get_click_position(input), Some((63, 63)));
+        assert_eq!(get_emote(input), Some(EMOTE_MASK as u8 - 1));
+        assert!(is_ready(input) && !is_shooting(input) && !is_meleeing(input));
+        assert!(direction(input).distance(Vec2::NEG_X) < 0.1);
+    }
+}
@@ -0,0 +1,127 @@
+use bevy::math::Vec3A;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_ggrs::RollbackFrameCount;
+use crate::{AppState, WORLD_SIZE};
+use crate::player_module::Player;
+use crate::projectile::Projectile;
+use crate::settings::GameSettings;
+
+/// Where a moving rollback entity was on the last two simulation frames it was
+/// seen on, for smoothing between them. Only ever read by rendering and the
+/// camera; the authoritative `Transform` is left alone.
+#[derive(Component, Clone, Copy)]
+pub struct Interpolated {
+    previous: Vec2,
+    current: Vec2,
+}
+
+impl Interpolated {
+    /// Position to draw the entity at this render frame
+    pub fn position(&self, fraction: &InterpolationFraction) -> Vec2 {
+        self.previous.lerp(self.current, fraction.0)
+    }
+}
+
+/// How far the renderer is from the previous simulation frame to the current one, from 0 to 1
+#[derive(Resource, Default)]
+pub struct InterpolationFraction(f32);
+
+/// Tracks when the simulation last advanced
+#[derive(Resource, Default)]
+struct TickClock {
+    last_frame: i32,
+    seconds_since_tick: f32,
+}
+
+/// Registers render interpolation to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<InterpolationFraction>()
+        .init_resource::<TickClock>()
+        .add_systems(
+            Update,
+            (start_interpolating, advance_interpolation)
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        )
+        .add_systems(
+            PostUpdate,
+            draw_interpolated
+                .after(TransformSystem::TransformPropagate)
+                .run_if(in_state(AppState::InGame)),
+        );
+}
+
+fn start_interpolating(
+    mut commands: Commands,
+    spawned: Query<(Entity, &Transform), (Or<(Added<Player>, Added<Projectile>)>, Without<Interpolated>)>,
+) {
+    for (entity, transform) in &spawned {
+        let position = transform.translation.xy();
+        commands.entity(entity).insert(Interpolated {
+            previous: position,
+            current: position,
+        });
+    }
+}
+
+/// Records new positions whenever the simulation advances and works out how
+/// far through the current frame rendering is
+pub(crate) fn advance_interpolation(
+    mut clock: ResMut<TickClock>,
+    mut fraction: ResMut<InterpolationFraction>,
+    mut entities: Query<(&mut Interpolated, &Transform)>,
+    frame: Option<Res<RollbackFrameCount>>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+) {
+    let frame = frame.map_or(0, |frame| frame.0);
+
+    if frame != clock.last_frame {
+        clock.last_frame = frame;
+        clock.seconds_since_tick = 0.0;
+        for (mut interpolated, transform) in &mut entities {
+            let position = transform.translation.xy();
+            // Don't slide across the whole map after wrapping over an edge
+            interpolated.previous = if interpolated.current.distance(position) > WORLD_SIZE as f32 / 2. {
+                position
+            } else {
+                interpolated.current
+            };
+            interpolated.current = position;
+        }
+    } else {
+        clock.seconds_since_tick += time.delta_seconds();
+    }
+
+    fraction.0 = (clock.seconds_since_tick / settings.frame_delta()).min(1.0);
+}
+
+/// Moves what's drawn, but not the entity itself, to its interpolated position
+///
+/// Runs after transform propagation, so children such as the gun are shifted
+/// by the same amount to stay attached.
+fn draw_interpolated(
+    fraction: Res<InterpolationFraction>,
+    mut entities: Query<(&Interpolated, &Transform, &mut GlobalTransform, Option<&Children>)>,
+    mut children_transforms: Query<&mut GlobalTransform, Without<Interpolated>>,
+) {
+    for (interpolated, transform, mut global_transform, children) in &mut entities {
+        let offset = Vec3A::from((interpolated.position(&fraction) - transform.translation.xy()).extend(0.0));
+        if offset == Vec3A::ZERO {
+            continue;
+        }
+
+        let mut affine = global_transform.affine();
+        affine.translation += offset;
+        *global_transform = affine.into();
+
+        for &child in children.into_iter().flatten() {
+            if let Ok(mut child_transform) = children_transforms.get_mut(child) {
+                let mut affine = child_transform.affine();
+                affine.translation += offset;
+                *child_transform = affine.into();
+            }
+        }
+    }
+}
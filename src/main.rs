@@ -4,9 +4,26 @@ mod network_manager;
 mod projectile;
 mod utilities;
 mod barriers;
+mod menus;
+mod camera;
+mod events;
+mod hud;
+mod effects;
+mod settings;
+mod fog_of_war;
+mod bots;
+mod chat;
+mod emotes;
+mod interpolation;
+mod maps;
+mod net_diagnostics;
+mod safe_zone;
+mod replay;
+mod practice;
+mod determinism;
 
+use std::hash::{Hash, Hasher};
 use bevy::prelude::*;
-use bevy::render::camera::ScalingMode;
 use bevy_ggrs::*;
 use bevy_matchbox::prelude::*;
 use bevy_asset_loader::prelude::*;
@@ -18,12 +35,13 @@ use crate::barriers::*;
 use crate::utilities::*;
 use crate::input_handler::*;
 use crate::network_manager::*;
+use crate::settings::GameSettings;
 
 /// Configuration for GGRS (Good Game Rollback System)
-type GameConfig = GgrsConfig<u32, PeerId>;
+type GameConfig = GgrsConfig<u64, PeerId>;
 
 /// Holds handles to game textures
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 struct GameTextures {
     #[asset(path = "bullet.png")]
     projectile_image: Handle<Image>,
@@ -40,160 +58,360 @@ struct GameTextures {
 pub enum AppState {
     #[default]
     Loading,
+    MainMenu,
     Connecting,
     InGame,
+    /// After a match has been won, showing the results screen
+    GameOver,
+    /// An asset failed to load, so the game can't start
+    AssetError,
 }
 
 /// Different phases during gameplay
 #[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default, Reflect)]
 enum GamePhase {
-    /// When players are actively playing
+    /// Before the first round, waiting for every player to ready up
     #[default]
+    Warmup,
+    /// When players are actively playing
     ActiveRound,
     /// After a round ends, transitioning to next
     RoundOver,
+    /// Tiebreaker round after a tied match, where the first kill wins
+    SuddenDeath,
+    /// After a player reaches the winning score
+    MatchOver,
 }
 
 pub const WORLD_SIZE: u32 = 41;
 pub const GRID_LINE_WIDTH: f32 = 0.05;
 
+/// Marker for the grid lines drawn under the map
+#[derive(Component)]
+struct GridLine;
+
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins,
-            player_module::plugin,
-            network_manager::plugin,
-            GgrsPlugin::<GameConfig>::default(),
-        ))
-        .init_state::<AppState>()
-        .init_resource::<RoundTimer>()
-        .init_resource::<MousePosition>()
-        .init_ggrs_state::<GamePhase>()
-        .add_loading_state(
-            LoadingState::new(AppState::Loading)
-                .load_collection::<GameTextures>()
-                .continue_to_state(AppState::Connecting),
-        )
-        // Register components and resources for rollback
-        .rollback_component_with_clone::<Transform>()
-        .rollback_resource_with_clone::<RoundTimer>()
-        .rollback_resource_with_clone::<PlayerScores>()
-        .rollback_component_with_copy::<CanAttack>()
-        .rollback_component_with_copy::<MovementDirection>()
-        .rollback_component_with_copy::<Projectile>()
-        .rollback_component_with_copy::<Player>()
-        .rollback_component_with_copy::<Barrier>()
-        // Set the background color
-        .insert_resource(ClearColor(Color::srgb(0.53, 0.53, 0.53)))
-        // Systems for when entering the Connecting state
-        .add_systems(OnEnter(AppState::Connecting), (initialize_game))
-        // Systems for when a new round starts
-        .add_systems(OnEnter(GamePhase::ActiveRound), create_world)
-        // Main game systems scheduled by GGRS
-        .add_systems(
-            GgrsSchedule,
-            (
-                player_module::move_players,
-                handle_barrier_collisions.after(player_module::move_players),
-                projectile_barrier_collisions.after(move_projectile),
-                projectile::reload_projectile,
-                projectile::fire_projectile
-                    .after(player_module::move_players)
-                    .after(projectile::reload_projectile)
-                    .after(handle_barrier_collisions),
-                move_projectile.after(projectile::fire_projectile),
-                check_player_collisions
-                    .after(move_projectile)
-                    .after(player_module::move_players),
-            )
-                .after(bevy_roll_safe::apply_state_transition::<GamePhase>)
-                .run_if(in_state(GamePhase::ActiveRound)),
-        )
-        // Systems for when the round has ended
-        .add_systems(
-            GgrsSchedule,
-            round_over_timer
-                .ambiguous_with(check_player_collisions)
-                .run_if(in_state(GamePhase::RoundOver))
-                .after(bevy_roll_safe::apply_state_transition::<GamePhase>),
-        )
-        // Additional game systems
-        .add_systems(
-            GgrsSchedule,
-            place_barrier_on_click.after(move_players),
-        )
-        .add_systems(
-            Update,
-            (
-                camera_follow.run_if(in_state(AppState::InGame)),
-                update_mouse_position.run_if(in_state(AppState::InGame)),
-            ),
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = settings::read_config_file(&args).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        std::process::exit(2);
+    });
+    // Flags on the command line override the settings file
+    let config_contents = config.as_ref().map(|config| config.contents.clone());
+    let mut settings = GameSettings::load(config_contents.as_deref(), &args).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        std::process::exit(2);
+    });
+    let setting_sources = settings::SettingSources::new(config.as_ref(), &args);
+    let mut launch_args = replay::LaunchArgs {
+        config: config_contents,
+        args: replay::gameplay_args(&args),
+    };
+
+    // A replay is played with the settings it was recorded with
+    let replay = settings.replay.clone().map(|path| {
+        let replay = replay::load_replay(&path).unwrap_or_else(|error| {
+            eprintln!("Could not play {}: {error}", path.display());
+            std::process::exit(2);
+        });
+        settings = GameSettings::load(replay.config.as_deref(), &replay.args)
+            .expect("Replay settings were validated when loading");
+        launch_args = replay::LaunchArgs {
+            config: replay.config.clone(),
+            args: replay.args.clone(),
+        };
+        replay
+    });
+
+    if let Some(frames) = settings.check_determinism {
+        match determinism::check(settings, frames) {
+            Ok(()) => {
+                println!("Simulation stayed deterministic for {frames} frames");
+                std::process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins,
+        menus::plugin,
+        camera::plugin,
+        hud::plugin,
+        effects::plugin,
+        fog_of_war::plugin,
+        chat::plugin,
+        interpolation::plugin,
+        net_diagnostics::plugin,
+        replay::plugin,
+    ));
+    add_simulation(&mut app, settings);
+    app.insert_resource(launch_args)
+        .insert_resource(setting_sources)
+        .add_systems(Startup, settings::log_setting_sources);
+    if let Some(replay) = replay {
+        app.insert_resource(replay::ReplayPlayback::new(replay));
+    }
+
+    app.add_loading_state(
+        LoadingState::new(AppState::Loading)
+            .load_collection::<GameTextures>()
+            .load_collection::<maps::GameMaps>()
+            .continue_to_state(AppState::MainMenu)
+            .on_failure_continue_to_state(AppState::AssetError),
+    )
+    .init_resource::<MousePosition>()
+    .init_resource::<PlacementSelector>()
+    // Set the background color
+    .insert_resource(ClearColor(Color::srgb(0.53, 0.53, 0.53)))
+    .add_systems(
+        Update,
+        (
+            update_mouse_position.run_if(in_state(AppState::InGame)),
+            input_handler::move_placement_selector
+                .run_if(in_state(AppState::InGame))
+                .run_if(building_enabled)
+                .run_if(chat::chat_closed),
+            input_handler::ready_up
+                .run_if(in_state(AppState::InGame))
+                .run_if(in_state(GamePhase::Warmup))
+                .run_if(chat::chat_closed),
+            practice::leave_practice_range
+                .run_if(in_state(AppState::InGame))
+                .run_if(resource_exists::<practice::PracticeRange>)
+                .run_if(chat::chat_closed),
+            finish_match
+                .run_if(in_state(AppState::InGame))
+                .run_if(in_state(GamePhase::MatchOver)),
+        ),
+    )
+    .add_systems(OnExit(AppState::GameOver), cleanup_match)
+    .add_systems(ReadInputs, input_handler::collect_player_inputs)
+    .run();
+}
+
+/// Adds everything that takes part in the rollback simulation
+///
+/// Kept apart from rendering, UI and input so the determinism check can run
+/// the same simulation in a headless app.
+fn add_simulation(app: &mut App, settings: GameSettings) {
+    let tick_rate = settings.tick_rate;
+
+    app.add_plugins((
+        GgrsPlugin::<GameConfig>::default(),
+        player_module::plugin,
+        network_manager::plugin,
+        events::plugin,
+        bots::plugin,
+        practice::plugin,
+        emotes::plugin,
+        maps::plugin,
+        safe_zone::plugin,
+    ))
+    .insert_resource(settings)
+    .set_rollback_schedule_fps(tick_rate)
+    .init_state::<AppState>()
+    .init_resource::<RoundTimer>()
+    .init_resource::<LocalReady>()
+    .init_resource::<GameRng>()
+    .init_resource::<RoundKills>()
+    .init_ggrs_state::<GamePhase>()
+    // Register components and resources for rollback
+    .rollback_component_with_clone::<Transform>()
+    .rollback_resource_with_copy::<RoundTimer>()
+    .rollback_resource_with_clone::<PlayerScores>()
+    .rollback_resource_with_clone::<PlayerStats>()
+    .rollback_resource_with_clone::<ReadyPlayers>()
+    .rollback_resource_with_copy::<MatchProgress>()
+    .rollback_resource_with_clone::<PlayerLives>()
+    .rollback_resource_with_clone::<KillStreaks>()
+    .rollback_resource_with_clone::<RoundKills>()
+    .rollback_resource_with_clone::<emotes::EmoteCooldowns>()
+    .rollback_resource_with_copy::<maps::MapRotation>()
+    .rollback_resource_with_clone::<GameRng>()
+    .rollback_resource_with_copy::<safe_zone::SafeZone>()
+    .rollback_component_with_copy::<CanAttack>()
+    .rollback_component_with_copy::<MeleeCooldown>()
+    .rollback_component_with_copy::<Health>()
+    .rollback_component_with_copy::<Burning>()
+    .rollback_component_with_copy::<MovementDirection>()
+    .rollback_component_with_copy::<Projectile>()
+    .rollback_component_with_copy::<Weapon>()
+    .rollback_component_with_copy::<Ammo>()
+    .rollback_component_with_copy::<Player>()
+    .rollback_component_with_copy::<Barrier>()
+    .rollback_component_with_copy::<BarrierDecay>()
+    .rollback_component_with_copy::<Slowed>()
+    .rollback_component_with_copy::<HeldClick>()
+    .rollback_component_with_copy::<StreakBoost>()
+    .rollback_component_with_copy::<SpawnMarker>()
+    // Checksum the state peers must agree on, so sync test sessions catch
+    // anything that comes out differently when a frame is simulated again
+    .checksum_component::<Transform>(checksum_transform)
+    .checksum_component_with_hash::<Health>()
+    .checksum_component_with_hash::<Ammo>()
+    .checksum_resource::<GameRng>(GameRng::fingerprint)
+    // Systems for when entering the Connecting state
+    .add_systems(
+        OnEnter(AppState::Connecting),
+        (initialize_game, input_handler::reset_local_ready),
+    )
+    // Systems for the warmup before the first round
+    .add_systems(OnEnter(GamePhase::Warmup), reset_ready_players)
+    .add_systems(
+        GgrsSchedule,
+        warmup_ready_check
+            .run_if(in_state(GamePhase::Warmup))
+            .after(bevy_roll_safe::apply_state_transition::<GamePhase>),
+    )
+    // Systems for when a new round starts
+    .add_systems(
+        OnEnter(GamePhase::ActiveRound),
+        (reseed_game_rng, create_world).chain(),
+    )
+    .add_systems(
+        OnEnter(GamePhase::SuddenDeath),
+        (reseed_game_rng, create_world).chain(),
+    )
+    .add_systems(
+        OnEnter(GamePhase::RoundOver),
+        (reset_round_timer, count_finished_round),
+    )
+    // Main game systems scheduled by GGRS
+    .add_systems(
+        GgrsSchedule,
+        (
+            player_module::materialize_players.before(player_module::move_players),
+            player_module::move_players,
+            handle_barrier_collisions.after(player_module::move_players),
+            projectile_barrier_collisions.after(move_projectile),
+            projectile::reload_projectile,
+            projectile::fire_projectile
+                .after(player_module::move_players)
+                .after(projectile::reload_projectile)
+                .after(handle_barrier_collisions),
+            move_projectile.after(projectile::fire_projectile),
+            player_module::melee_attacks
+                .after(projectile::fire_projectile)
+                .before(move_projectile),
+            check_player_collisions
+                .after(move_projectile)
+                .after(player_module::move_players)
+                .after(projectile_barrier_collisions),
+            player_module::explode_grenades
+                .after(check_player_collisions)
+                .after(projectile_barrier_collisions),
+            player_module::tick_burning.after(player_module::explode_grenades),
+            player_module::damage_outside_safe_zone
+                .after(player_module::tick_burning)
+                .after(safe_zone::shrink_safe_zone)
+                .run_if(safe_zone::safe_zone_enabled),
+            player_module::grant_streak_rewards.after(player_module::damage_outside_safe_zone),
+            player_module::decide_round.after(player_module::grant_streak_rewards),
         )
-        .add_systems(ReadInputs, input_handler::collect_player_inputs)
-        .run();
+            .after(bevy_roll_safe::apply_state_transition::<GamePhase>)
+            .run_if(in_state(GamePhase::ActiveRound).or_else(in_state(GamePhase::SuddenDeath))),
+    )
+    // Systems for when the round has ended
+    .add_systems(
+        GgrsSchedule,
+        round_over_timer
+            .ambiguous_with(check_player_collisions)
+            .run_if(in_state(GamePhase::RoundOver))
+            .after(bevy_roll_safe::apply_state_transition::<GamePhase>),
+    )
+    // Additional game systems
+    .add_systems(
+        GgrsSchedule,
+        (
+            place_barrier_on_click.after(move_players).run_if(building_enabled),
+            decay_barriers,
+        ),
+    );
 }
 
 /// Initializes the game setup
-fn initialize_game(mut commands: Commands) {
-    // Set up the main camera with fixed vertical scaling
-    let mut camera_bundle = Camera2dBundle::default();
-    camera_bundle.projection.scaling_mode = ScalingMode::FixedVertical(15.0);
-    commands.spawn((camera_bundle, Name::new("Main Camera")));
+/// Hashes the exact bits of a position, since even the smallest difference
+/// between peers grows into a desync
+fn checksum_transform(transform: &Transform) -> u64 {
+    let mut hasher = checksum_hasher();
+    transform.translation.to_array().map(f32::to_bits).hash(&mut hasher);
+    hasher.finish()
+}
 
+fn initialize_game(mut commands: Commands, settings: Res<GameSettings>) {
     // Draw horizontal grid lines
     for i in 0..=WORLD_SIZE {
-        commands.spawn(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(
-                0.,
-                i as f32 - WORLD_SIZE as f32 / 2.,
-                0.,
-            )),
-            sprite: Sprite {
-                color: Color::srgb(0.27, 0.27, 0.27),
-                custom_size: Some(Vec2::new(WORLD_SIZE as f32, GRID_LINE_WIDTH)),
+        commands.spawn((
+            GridLine,
+            SpriteBundle {
+                transform: Transform::from_translation(Vec3::new(
+                    0.,
+                    i as f32 - WORLD_SIZE as f32 / 2.,
+                    0.,
+                )),
+                sprite: Sprite {
+                    color: Color::srgb(0.27, 0.27, 0.27),
+                    custom_size: Some(Vec2::new(WORLD_SIZE as f32, GRID_LINE_WIDTH)),
+                    ..default()
+                },
                 ..default()
             },
-            ..default()
-        });
+        ));
     }
     // Draw vertical grid lines
     for i in 0..=WORLD_SIZE {
-        commands.spawn(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(
-                i as f32 - WORLD_SIZE as f32 / 2.,
-                0.,
-                0.,
-            )),
-            sprite: Sprite {
-                color: Color::srgb(0.27, 0.27, 0.27),
-                custom_size: Some(Vec2::new(GRID_LINE_WIDTH, WORLD_SIZE as f32)),
+        commands.spawn((
+            GridLine,
+            SpriteBundle {
+                transform: Transform::from_translation(Vec3::new(
+                    i as f32 - WORLD_SIZE as f32 / 2.,
+                    0.,
+                    0.,
+                )),
+                sprite: Sprite {
+                    color: Color::srgb(0.27, 0.27, 0.27),
+                    custom_size: Some(Vec2::new(GRID_LINE_WIDTH, WORLD_SIZE as f32)),
+                    ..default()
+                },
                 ..default()
             },
-            ..default()
-        });
+        ));
     }
 
     let player_scores = PlayerScores::new();
     commands.insert_resource(player_scores);
+    commands.insert_resource(PlayerStats::new());
+    commands.insert_resource(ReadyPlayers::new());
+    commands.insert_resource(MatchProgress::default());
+    commands.insert_resource(PlayerLives::new(settings.lives));
+    commands.insert_resource(KillStreaks::new());
+    commands.insert_resource(RoundTimer::default());
 }
 
-/// Makes the camera follow the local player_module
-fn camera_follow(
-    local_players: Res<LocalPlayers>,
-    player_query: Query<(&Player, &Transform)>,
-    mut camera_query: Query<&mut Transform, (With<Camera>, Without<Player>)>,
-) {
-    for (player, player_transform) in &player_query {
-        // Only follow the local player_module
-        if !local_players.0.contains(&player.handle) {
-            continue;
-        }
-
-        let position = player_transform.translation;
+/// Leaves the match once the rollback simulation has declared a winner
+fn finish_match(mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::GameOver);
+}
 
-        for mut transform in &mut camera_query {
-            transform.translation.x = position.x;
-            transform.translation.y = position.y;
-        }
+/// Tears down everything left over from a finished match so a new one can start
+fn cleanup_match(
+    mut commands: Commands,
+    match_entities: Query<
+        Entity,
+        Or<(With<Player>, With<SpawnMarker>, With<Projectile>, With<Barrier>, With<GridLine>)>,
+    >,
+    mut next_phase: ResMut<NextState<GamePhase>>,
+) {
+    for entity in &match_entities {
+        commands.entity(entity).despawn_recursive();
     }
-}
\ No newline at end of file
+
+    // The phase is only applied inside the rollback schedule, so the next
+    // session starts its first frame by going back to warmup
+    next_phase.set(GamePhase::Warmup);
+}
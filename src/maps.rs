@@ -0,0 +1,299 @@
+use std::fmt;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+use serde::Deserialize;
+use crate::{AppState, GamePhase, WORLD_SIZE};
+use crate::network_manager::NUM_PLAYERS;
+use crate::settings::GameSettings;
+
+/// Names of the maps shipped in `assets/maps`, as accepted by `--map`
+pub const PRESET_MAPS: [&str; 2] = ["crossroads", "fortress"];
+
+/// Which layout the barriers are placed from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MapChoice {
+    /// Walls generated from the match seed
+    #[default]
+    Random,
+    /// One of the `PRESET_MAPS`
+    Preset(String),
+}
+
+/// A hand-designed layout of barrier cells, loaded from a `.map.ron` file
+#[derive(Asset, TypePath, Deserialize, Debug)]
+pub struct Map {
+    pub name: String,
+    /// One string per row from the top of the map, `#` for a barrier and `.` for open floor
+    rows: Vec<String>,
+    /// Cells players spawn in by handle, as (column, row) from the top left.
+    /// Players spawn randomly when there are none.
+    #[serde(default)]
+    spawns: Vec<(u32, u32)>,
+}
+
+impl Map {
+    /// Grid cells holding a barrier, with y counted up from the bottom like the world
+    pub fn barrier_cells(&self) -> impl Iterator<Item = UVec2> + '_ {
+        self.rows.iter().enumerate().flat_map(|(row, cells)| {
+            cells
+                .chars()
+                .enumerate()
+                .filter(|&(_, cell)| cell == '#')
+                .map(move |(column, _)| row_to_cell(column, row))
+        })
+    }
+
+    /// World position each player spawns at by handle, if the map defines spawn points
+    pub fn spawn_points(&self) -> Option<Vec<Vec2>> {
+        if self.spawns.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.spawns
+                .iter()
+                .map(|&(column, row)| cell_center(row_to_cell(column as usize, row as usize)))
+                .collect(),
+        )
+    }
+
+    /// Checks the map fits the world grid and every spawn point is usable
+    fn validate(&self) -> Result<(), MapLoadError> {
+        let size = WORLD_SIZE as usize;
+        if self.rows.len() != size {
+            return Err(MapLoadError::Invalid(format!(
+                "expected {size} rows, found {}",
+                self.rows.len()
+            )));
+        }
+
+        for (row, cells) in self.rows.iter().enumerate() {
+            if cells.chars().count() != size {
+                return Err(MapLoadError::Invalid(format!(
+                    "row {row} should be {size} cells long"
+                )));
+            }
+            if let Some(cell) = cells.chars().find(|&cell| cell != '#' && cell != '.') {
+                return Err(MapLoadError::Invalid(format!(
+                    "row {row} has unknown cell {cell:?}"
+                )));
+            }
+        }
+
+        if !self.spawns.is_empty() && self.spawns.len() < NUM_PLAYERS {
+            return Err(MapLoadError::Invalid(format!(
+                "needs a spawn point for each of the {NUM_PLAYERS} players"
+            )));
+        }
+
+        for &(column, row) in &self.spawns {
+            let on_barrier = self
+                .rows
+                .get(row as usize)
+                .and_then(|cells| cells.chars().nth(column as usize))
+                .map_or(true, |cell| cell == '#');
+            if on_barrier {
+                return Err(MapLoadError::Invalid(format!(
+                    "spawn point ({column}, {row}) is outside the map or on a barrier"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts a column and row from the top of a map file to a world grid cell
+fn row_to_cell(column: usize, row: usize) -> UVec2 {
+    UVec2::new(column as u32, WORLD_SIZE - 1 - row as u32)
+}
+
+/// World position of the middle of a grid cell
+pub fn cell_center(cell: UVec2) -> Vec2 {
+    cell.as_vec2() + Vec2::splat(0.5) - Vec2::splat(WORLD_SIZE as f32 / 2.)
+}
+
+/// Why a map file couldn't be loaded
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapLoadError::Io(error) => write!(f, "could not read map: {error}"),
+            MapLoadError::Parse(error) => write!(f, "could not parse map: {error}"),
+            MapLoadError::Invalid(reason) => write!(f, "invalid map: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {}
+
+impl From<std::io::Error> for MapLoadError {
+    fn from(error: std::io::Error) -> Self {
+        MapLoadError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for MapLoadError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        MapLoadError::Parse(error)
+    }
+}
+
+#[derive(Default)]
+struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    type Asset = Map;
+    type Settings = ();
+    type Error = MapLoadError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Map, MapLoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let map: Map = ron::de::from_bytes(&bytes)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+/// Handles to every preset map
+#[derive(AssetCollection, Resource, Default)]
+pub struct GameMaps {
+    #[asset(path = "maps/crossroads.map.ron")]
+    crossroads: Handle<Map>,
+
+    #[asset(path = "maps/fortress.map.ron")]
+    fortress: Handle<Map>,
+}
+
+impl GameMaps {
+    fn get(&self, name: &str) -> Option<&Handle<Map>> {
+        match name {
+            "crossroads" => Some(&self.crossroads),
+            "fortress" => Some(&self.fortress),
+            _ => None,
+        }
+    }
+}
+
+/// Position in the map rotation, advanced every time a round ends
+#[derive(Resource, Default, Clone, Copy)]
+pub struct MapRotation {
+    index: usize,
+}
+
+/// The map the current round is played on, from the rotation in the settings
+#[derive(SystemParam)]
+pub struct ChosenMap<'w> {
+    settings: Res<'w, GameSettings>,
+    rotation: Res<'w, MapRotation>,
+    maps: Res<'w, GameMaps>,
+    assets: Res<'w, Assets<Map>>,
+}
+
+impl ChosenMap<'_> {
+    /// The preset to build the world from, or `None` for random walls
+    pub fn get(&self) -> Option<&Map> {
+        let rotation = &self.settings.map_rotation;
+        match rotation.get(self.rotation.index % rotation.len().max(1))? {
+            MapChoice::Random => None,
+            MapChoice::Preset(name) => self
+                .maps
+                .get(name)
+                .and_then(|handle| self.assets.get(handle)),
+        }
+    }
+}
+
+/// Registers the map asset and rotation to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_asset::<Map>()
+        .init_asset_loader::<MapLoader>()
+        .init_resource::<MapRotation>()
+        .add_systems(OnEnter(AppState::Connecting), reset_map_rotation)
+        .add_systems(OnEnter(GamePhase::RoundOver), advance_map_rotation);
+}
+
+fn reset_map_rotation(mut rotation: ResMut<MapRotation>) {
+    *rotation = MapRotation::default();
+}
+
+/// Moves on to the next map in the rotation, for the round after this one
+fn advance_map_rotation(mut rotation: ResMut<MapRotation>) {
+    rotation.index += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_preset(name: &str) -> Map {
+        let path = format!("{}/assets/maps/{name}.map.ron", env!("CARGO_MANIFEST_DIR"));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        ron::from_str(&contents).unwrap()
+    }
+
+    /// An empty map with a single barrier in the top left corner
+    fn corner_map() -> Map {
+        let size = WORLD_SIZE as usize;
+        let mut rows = vec![".".repeat(size); size];
+        rows[0].replace_range(0..1, "#");
+        Map {
+            name: "corner".to_string(),
+            rows,
+            spawns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn preset_maps_are_valid() {
+        for name in PRESET_MAPS {
+            let map = read_preset(name);
+            map.validate().unwrap_or_else(|error| panic!("{name}: {error}"));
+            assert!(map.barrier_cells().next().is_some(), "{name} has no barriers");
+        }
+    }
+
+    #[test]
+    fn rows_are_read_from_the_top_of_the_map() {
+        let cells: Vec<UVec2> = corner_map().barrier_cells().collect();
+        assert_eq!(cells, vec![UVec2::new(0, WORLD_SIZE - 1)]);
+    }
+
+    #[test]
+    fn rejects_bad_layouts_and_spawns() {
+        let mut short = corner_map();
+        short.rows.pop();
+        assert!(short.validate().is_err());
+
+        let mut unknown_cell = corner_map();
+        unknown_cell.rows[1].replace_range(0..1, "x");
+        assert!(unknown_cell.validate().is_err());
+
+        let mut spawn_on_barrier = corner_map();
+        spawn_on_barrier.spawns = (0..NUM_PLAYERS as u32).map(|row| (0, row)).collect();
+        assert!(spawn_on_barrier.validate().is_err());
+
+        let mut spawns = corner_map();
+        spawns.spawns = (0..NUM_PLAYERS as u32).map(|row| (1, row)).collect();
+        assert!(spawns.validate().is_ok());
+    }
+}
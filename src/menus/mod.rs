@@ -0,0 +1,270 @@
+use bevy::asset::AssetLoadFailedEvent;
+use bevy::prelude::*;
+use crate::AppState;
+use crate::maps::Map;
+use crate::bots::HumanPlayers;
+use crate::network_manager::{LobbyStatus, Opponents, NUM_PLAYERS};
+use crate::utilities::{PlayerScores, PlayerStats};
+
+const TEXT_COLOR: Color = Color::srgb(0.95, 0.95, 0.95);
+const BUTTON_COLOR: Color = Color::srgb(0.2, 0.2, 0.2);
+const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.3, 0.3, 0.3);
+
+/// Registers the menu screens to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<FailedAssets>()
+        .add_systems(
+            Update,
+            (record_failed_assets::<Image>, record_failed_assets::<Map>)
+                .run_if(in_state(AppState::Loading)),
+        )
+        .add_systems(OnEnter(AppState::AssetError), spawn_asset_error_screen)
+        .add_systems(OnEnter(AppState::MainMenu), spawn_main_menu)
+        .add_systems(OnExit(AppState::MainMenu), despawn_screen::<MainMenuScreen>)
+        .add_systems(
+            OnEnter(AppState::Connecting),
+            spawn_lobby_screen.run_if(resource_equals(Opponents::Online)),
+        )
+        .add_systems(OnExit(AppState::Connecting), despawn_screen::<LobbyScreen>)
+        .add_systems(OnEnter(AppState::GameOver), spawn_results_screen)
+        .add_systems(OnExit(AppState::GameOver), despawn_screen::<ResultsScreen>)
+        .add_systems(
+            Update,
+            (
+                button_hover_color,
+                menu_button_actions.run_if(
+                    in_state(AppState::MainMenu).or_else(in_state(AppState::GameOver)),
+                ),
+                update_lobby_status
+                    .run_if(in_state(AppState::Connecting))
+                    .run_if(resource_exists::<LobbyStatus>),
+            ),
+        );
+}
+
+/// Marker for everything on the main menu
+#[derive(Component)]
+struct MainMenuScreen;
+
+/// Marker for everything on the screen shown while waiting for an online match
+#[derive(Component)]
+struct LobbyScreen;
+
+/// Marker for the text saying what the lobby is waiting on
+#[derive(Component)]
+struct LobbyStatusText;
+
+/// Marker for everything on the post-match results screen
+#[derive(Component)]
+struct ResultsScreen;
+
+/// Assets that failed to load, with the reason each one failed
+#[derive(Resource, Default)]
+struct FailedAssets(Vec<String>);
+
+/// What a menu button does when pressed
+#[derive(Component, Clone, Copy)]
+enum MenuButton {
+    Play,
+    PlayBots,
+    Practice,
+    PlayAgain,
+}
+
+/// Despawns every entity belonging to a screen
+fn despawn_screen<T: Component>(mut commands: Commands, screen: Query<Entity, With<T>>) {
+    for entity in &screen {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Builds a full-screen column layout for a menu
+fn screen_root() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            row_gap: Val::Px(12.0),
+            ..default()
+        },
+        background_color: Color::srgba(0.0, 0.0, 0.0, 0.6).into(),
+        ..default()
+    }
+}
+
+/// Builds a text section with the menu style
+fn menu_text(text: impl Into<String>, font_size: f32) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font_size,
+            color: TEXT_COLOR,
+            ..default()
+        },
+    )
+}
+
+/// Spawns a labelled button as a child of a menu
+fn spawn_button(parent: &mut ChildBuilder, label: &str, action: MenuButton) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::axes(Val::Px(24.0), Val::Px(12.0)),
+                    margin: UiRect::top(Val::Px(16.0)),
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            action,
+        ))
+        .with_children(|button| {
+            button.spawn(menu_text(label, 28.0));
+        });
+}
+
+fn spawn_main_menu(mut commands: Commands) {
+    commands
+        .spawn((screen_root(), MainMenuScreen))
+        .with_children(|parent| {
+            parent.spawn(menu_text("Super Cool 2D PVP Shooter Game", 48.0));
+            spawn_button(parent, "Play", MenuButton::Play);
+            spawn_button(parent, "Play vs bot", MenuButton::PlayBots);
+            spawn_button(parent, "Practice range", MenuButton::Practice);
+        });
+}
+
+fn spawn_lobby_screen(mut commands: Commands) {
+    commands
+        .spawn((screen_root(), LobbyScreen))
+        .with_children(|parent| {
+            parent.spawn((menu_text("Connecting...", 36.0), LobbyStatusText));
+        });
+}
+
+/// Shows whether the lobby is waiting for players to connect or to finish loading
+fn update_lobby_status(lobby: Res<LobbyStatus>, mut status: Query<&mut Text, With<LobbyStatusText>>) {
+    for mut text in &mut status {
+        text.sections[0].value = if lobby.connected < NUM_PLAYERS {
+            format!("Waiting for players to connect ({}/{NUM_PLAYERS})", lobby.connected)
+        } else if lobby.loaded < NUM_PLAYERS {
+            format!("Waiting for players to load ({}/{NUM_PLAYERS})", lobby.loaded)
+        } else {
+            String::from("Starting...")
+        };
+    }
+}
+
+/// Shows every player's final score, kills and deaths, best first
+fn spawn_results_screen(
+    mut commands: Commands,
+    player_scores: Res<PlayerScores>,
+    player_stats: Res<PlayerStats>,
+    humans: HumanPlayers,
+) {
+    let mut standings: Vec<usize> = (0..NUM_PLAYERS).collect();
+    standings.sort_by_key(|&handle| {
+        (
+            std::cmp::Reverse(player_scores.get(handle)),
+            std::cmp::Reverse(player_stats.kills(handle)),
+            player_stats.deaths(handle),
+        )
+    });
+
+    commands
+        .spawn((screen_root(), ResultsScreen))
+        .with_children(|parent| {
+            parent.spawn(menu_text("Match Over", 48.0));
+
+            for (place, &handle) in standings.iter().enumerate() {
+                let name = if humans.contains(handle) {
+                    format!("Player {} (you)", handle + 1)
+                } else {
+                    format!("Player {}", handle + 1)
+                };
+
+                parent.spawn(menu_text(
+                    format!(
+                        "{}. {}  -  score {}  kills {}  deaths {}  barriers destroyed {}",
+                        place + 1,
+                        name,
+                        player_scores.get(handle),
+                        player_stats.kills(handle),
+                        player_stats.deaths(handle),
+                        player_stats.barriers_destroyed(handle),
+                    ),
+                    28.0,
+                ));
+            }
+
+            spawn_button(parent, "Play again", MenuButton::PlayAgain);
+        });
+}
+
+/// Logs every asset that fails to load and keeps it for the error screen
+fn record_failed_assets<A: Asset>(
+    mut failures: EventReader<AssetLoadFailedEvent<A>>,
+    mut failed_assets: ResMut<FailedAssets>,
+) {
+    for failure in failures.read() {
+        error!("Failed to load asset {}: {}", failure.path, failure.error);
+        failed_assets.0.push(format!("{}: {}", failure.path, failure.error));
+    }
+}
+
+/// Explains which assets are missing instead of leaving a blank window
+fn spawn_asset_error_screen(mut commands: Commands, failed_assets: Res<FailedAssets>) {
+    commands.spawn(screen_root()).with_children(|parent| {
+        parent.spawn(menu_text("Could not load the game's assets", 48.0));
+        for failure in &failed_assets.0 {
+            parent.spawn(menu_text(failure, 20.0));
+        }
+        parent.spawn(menu_text(
+            "Check the assets folder is next to the game and reinstall if any files are missing",
+            24.0,
+        ));
+    });
+}
+
+fn button_hover_color(
+    mut buttons: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<MenuButton>)>,
+) {
+    for (interaction, mut background) in &mut buttons {
+        *background = match interaction {
+            Interaction::Hovered | Interaction::Pressed => BUTTON_HOVERED_COLOR.into(),
+            Interaction::None => BUTTON_COLOR.into(),
+        };
+    }
+}
+
+fn menu_button_actions(
+    buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut opponents: ResMut<Opponents>,
+) {
+    for (interaction, action) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match action {
+            MenuButton::Play => {
+                *opponents = Opponents::Online;
+                next_state.set(AppState::Connecting);
+            }
+            MenuButton::PlayBots => {
+                *opponents = Opponents::Bots;
+                next_state.set(AppState::Connecting);
+            }
+            MenuButton::Practice => {
+                *opponents = Opponents::Practice;
+                next_state.set(AppState::Connecting);
+            }
+            MenuButton::PlayAgain => next_state.set(AppState::MainMenu),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use bevy_ggrs::{ConfirmedFrameCount, LocalPlayers, RollbackFrameCount, Session};
+use crate::{AppState, GameConfig};
+use crate::network_manager::NUM_PLAYERS;
+
+/// Key that shows and hides the diagnostics overlay
+const TOGGLE_OVERLAY_KEY: KeyCode = KeyCode::F3;
+
+/// Input buffering as seen by one player's peer connection
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlayerInputDiagnostics {
+    pub handle: usize,
+    /// Whether the player's inputs are read on this machine
+    pub local: bool,
+    /// Local inputs sent to this player that they haven't acknowledged yet
+    pub unacknowledged_inputs: usize,
+    /// Round trip time to the player, in milliseconds
+    pub ping: u128,
+    /// How many frames this client is behind the player, negative when ahead
+    pub frames_behind: i32,
+    /// Whether this player's inputs are currently guessed rather than confirmed
+    pub predicting: bool,
+}
+
+/// Input buffering and prediction read from the GGRS session every frame
+///
+/// Read-only instrumentation for tuning input delay; nothing in the
+/// simulation reads it.
+#[derive(Resource, Default, Debug)]
+pub struct InputDiagnostics {
+    /// Frames simulated ahead of the last frame every input is confirmed for
+    pub frames_predicted: i32,
+    pub players: Vec<PlayerInputDiagnostics>,
+}
+
+/// Whether the diagnostics overlay is visible
+#[derive(Resource, Default)]
+struct OverlayVisible(bool);
+
+/// Text of the diagnostics overlay
+#[derive(Component)]
+struct DiagnosticsOverlay;
+
+/// Registers the input diagnostics and their overlay to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<InputDiagnostics>()
+        .init_resource::<OverlayVisible>()
+        .add_systems(OnEnter(AppState::InGame), spawn_overlay)
+        .add_systems(OnExit(AppState::InGame), (despawn_overlay, reset_diagnostics))
+        .add_systems(
+            Update,
+            (
+                read_input_diagnostics.run_if(resource_exists::<Session<GameConfig>>),
+                toggle_overlay,
+                update_overlay,
+            )
+                .chain()
+                .run_if(in_state(AppState::InGame)),
+        );
+}
+
+fn read_input_diagnostics(
+    mut diagnostics: ResMut<InputDiagnostics>,
+    session: Res<Session<GameConfig>>,
+    local_players: Option<Res<LocalPlayers>>,
+    frame: Option<Res<RollbackFrameCount>>,
+    confirmed_frame: Option<Res<ConfirmedFrameCount>>,
+) {
+    let frame = frame.map_or(0, |frame| frame.0);
+    let confirmed_frame = confirmed_frame.map_or(frame, |confirmed| confirmed.0);
+    diagnostics.frames_predicted = (frame - confirmed_frame).max(0);
+
+    let is_local = |handle| local_players.as_ref().is_some_and(|local| local.0.contains(&handle));
+    let frames_predicted = diagnostics.frames_predicted;
+
+    diagnostics.players = (0..NUM_PLAYERS)
+        .map(|handle| {
+            let local = is_local(handle);
+            let mut player = PlayerInputDiagnostics {
+                handle,
+                local,
+                predicting: !local && frames_predicted > 0,
+                ..default()
+            };
+
+            // Only peer to peer sessions have a connection to each player
+            if let Session::P2P(session) = &*session {
+                if let Ok(stats) = session.network_stats(handle) {
+                    player.unacknowledged_inputs = stats.send_queue_len;
+                    player.ping = stats.ping;
+                    player.frames_behind = stats.local_frames_behind;
+                }
+            }
+
+            player
+        })
+        .collect();
+}
+
+fn toggle_overlay(keyboard_input: Res<ButtonInput<KeyCode>>, mut visible: ResMut<OverlayVisible>) {
+    if keyboard_input.just_pressed(TOGGLE_OVERLAY_KEY) {
+        visible.0 = !visible.0;
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        DiagnosticsOverlay,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 16.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(12.0),
+            bottom: Val::Px(12.0),
+            ..default()
+        }),
+    ));
+}
+
+fn update_overlay(
+    diagnostics: Res<InputDiagnostics>,
+    visible: Res<OverlayVisible>,
+    mut overlay: Query<(&mut Text, &mut Visibility), With<DiagnosticsOverlay>>,
+) {
+    for (mut text, mut visibility) in &mut overlay {
+        if !visible.0 {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        *visibility = Visibility::Inherited;
+
+        let mut lines = vec![format!("Predicted frames: {}", diagnostics.frames_predicted)];
+        for player in &diagnostics.players {
+            lines.push(if player.local {
+                format!("Player {} (local)", player.handle + 1)
+            } else {
+                format!(
+                    "Player {}: {} unacked, ping {} ms, {} frames behind{}",
+                    player.handle + 1,
+                    player.unacknowledged_inputs,
+                    player.ping,
+                    player.frames_behind,
+                    if player.predicting { ", predicting" } else { "" },
+                )
+            });
+        }
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+fn despawn_overlay(mut commands: Commands, overlay: Query<Entity, With<DiagnosticsOverlay>>) {
+    for entity in &overlay {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn reset_diagnostics(mut diagnostics: ResMut<InputDiagnostics>) {
+    *diagnostics = InputDiagnostics::default();
+}
@@ -1,83 +1,266 @@
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
 use bevy_matchbox::prelude::*;
 use bevy_ggrs::*;
 use crate::AppState;
 use crate::GameConfig;
+use crate::settings::GameSettings;
 
 /// Resource for storing the game's random seed
 #[derive(Resource, Default, Clone, Copy, Debug, Deref, DerefMut)]
-pub struct RandomSeed(u64);
+pub struct RandomSeed(pub(crate) u64);
 
 pub (crate) const NUM_PLAYERS: usize = 2;
 
+/// Socket channel carrying GGRS packets, handed over to the session
+const GGRS_CHANNEL: usize = 0;
+/// Reliable socket channel for chat, kept by the socket for the whole match
+pub(crate) const CHAT_CHANNEL: usize = 1;
+/// Reliable socket channel peers announce they've finished loading on, before the session starts
+const LOBBY_CHANNEL: usize = 2;
+/// Sent to every peer that connects, since a peer only reaches the lobby once its assets are loaded
+const LOADED_MESSAGE: &[u8] = b"loaded";
+
+/// How long the lobby stays open for spectators once enough players have joined
+const SPECTATOR_GRACE_SECONDS: f32 = 3.0;
+
+/// Whether the local peer controls a player or only watches the match
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LocalRole {
+    #[default]
+    Player,
+    Spectator,
+}
+
+/// Who the local player is matched against, chosen on the main menu
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Opponents {
+    /// Other peers in the matchbox room
+    #[default]
+    Online,
+    /// Bots on this machine, without any networking
+    Bots,
+    /// Nobody, every player's inputs come from a replay file
+    Replay,
+    /// Target dummies on the practice range, without any networking
+    Practice,
+}
+
+/// Handle of every peer in the match, with spectators numbered after the players
+#[derive(Resource, Default, Deref)]
+pub struct PeerHandles(HashMap<PeerId, usize>);
+
+/// How many players are connected and loaded, counting this one, shown while connecting
+#[derive(Resource, Default)]
+pub struct LobbyStatus {
+    pub connected: usize,
+    pub loaded: usize,
+    /// Peers that have said they finished loading the game's assets
+    loaded_peers: HashSet<PeerId>,
+}
+
+/// Counts down the spectator grace period before the session starts
+#[derive(Resource, Deref, DerefMut)]
+struct LobbyTimer(Timer);
+
+impl Default for LobbyTimer {
+    fn default() -> Self {
+        LobbyTimer(Timer::from_seconds(SPECTATOR_GRACE_SECONDS, TimerMode::Once))
+    }
+}
+
 /// Registers the networking systems to the app
 pub(super) fn plugin(app: &mut App) {
-    app.add_systems(OnEnter(AppState::Connecting), initialize_socket)
+    app.init_resource::<LocalRole>()
+        .init_resource::<Opponents>()
+        .add_systems(
+            OnEnter(AppState::Connecting),
+            initialize_socket.run_if(resource_equals(Opponents::Online)),
+        )
         .add_systems(
             Update,
-            wait_for_players.run_if(in_state(AppState::Connecting)),
-        );
+            wait_for_players
+                .run_if(in_state(AppState::Connecting))
+                .run_if(resource_equals(Opponents::Online)),
+        )
+        .add_systems(GgrsSchedule, confirm_local_frames.run_if(local_session))
+        .add_systems(OnEnter(AppState::GameOver), end_session);
+}
+
+/// Whether the match runs entirely on this machine, against bots, dummies or
+/// a replay, in a sync test session that never rolls back
+pub fn local_session(session: Option<Res<Session<GameConfig>>>) -> bool {
+    matches!(session.as_deref(), Some(Session::SyncTest(_)))
+}
+
+/// Marks every simulated frame as confirmed, since a local session is never rolled back
+fn confirm_local_frames(frame: Res<RollbackFrameCount>, mut confirmed_frame: ResMut<ConfirmedFrameCount>) {
+    confirmed_frame.0 = frame.0;
 }
 
 /// Initializes the network socket for matchmaking
-fn initialize_socket(mut commands: Commands) {
-    let matchbox_url =
-        String::from("ws://0.0.0.0:3536/cis1905?next=$") + &NUM_PLAYERS.to_string();
+fn initialize_socket(mut commands: Commands, settings: Res<GameSettings>) {
+    // Everyone in the room is connected to everyone else, so peers beyond
+    // NUM_PLAYERS can join the same match as spectators
+    let matchbox_url = format!("ws://0.0.0.0:3536/{}", settings.room);
     info!("Connecting to {}", matchbox_url);
-    commands.insert_resource(MatchboxSocket::new_ggrs(matchbox_url));
+
+    // Chat gets its own reliable channel so it never touches GGRS packets
+    let socket_builder = WebRtcSocketBuilder::new(matchbox_url)
+        .add_ggrs_channel()
+        .add_reliable_channel()
+        .add_reliable_channel();
+    commands.insert_resource(MatchboxSocket::from(socket_builder));
+    commands.init_resource::<LobbyTimer>();
+    commands.insert_resource(LobbyStatus::default());
 }
 
-/// Waits for all players to connect before starting the game
+/// Waits for all players to connect and finish loading before starting the game
+///
+/// Roles are assigned by sorting every peer ID in the room, so all peers agree
+/// on them without further communication: the first NUM_PLAYERS are players
+/// and the rest are spectators. The first player hosts the spectators.
+///
+/// Peers load assets independently, so each one tells every peer that
+/// connects that it's loaded, and the session only starts once every other
+/// player has said so.
 fn wait_for_players(
     mut commands: Commands,
-    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut lobby_timer: ResMut<LobbyTimer>,
+    mut lobby: ResMut<LobbyStatus>,
     mut next_state: ResMut<NextState<AppState>>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
 ) {
     // If the channel isn't ready yet, just return
-    if socket.get_channel(0).is_err() {
+    if socket.get_channel(GGRS_CHANNEL).is_err() {
         return;
     }
 
-    socket.update_peers();
-    let connected_players = socket.players();
+    // This peer only reaches the lobby once loading has finished
+    for (peer, state) in socket.update_peers() {
+        match state {
+            PeerState::Connected => socket.channel_mut(LOBBY_CHANNEL).send(LOADED_MESSAGE.into(), peer),
+            PeerState::Disconnected => {
+                lobby.loaded_peers.remove(&peer);
+            }
+        }
+    }
+    for (peer, packet) in socket.channel_mut(LOBBY_CHANNEL).receive() {
+        if *packet == *LOADED_MESSAGE {
+            lobby.loaded_peers.insert(peer);
+        }
+    }
+    let Some(own_id) = socket.id() else {
+        return;
+    };
+
+    let mut peers: Vec<PeerId> = socket.connected_peers().collect();
+    peers.push(own_id);
+    peers.sort_by_key(|peer| peer.0.as_u64_pair());
 
     let required_players = NUM_PLAYERS;
-    if connected_players.len() < required_players {
+    let players = &peers[..peers.len().min(required_players)];
+    lobby.connected = players.len();
+    lobby.loaded = players
+        .iter()
+        .filter(|&&peer| peer == own_id || lobby.loaded_peers.contains(&peer))
+        .count();
+    if peers.len() < required_players {
         info!(
             "Waiting for {} more player(s)...",
-            required_players - connected_players.len()
+            required_players - peers.len()
         );
+        lobby_timer.reset();
+        return;
+    }
+    if lobby.loaded < required_players {
+        lobby_timer.reset();
+        return;
+    }
+
+    // Give spectators a moment to join before the session is locked in
+    lobby_timer.tick(time.delta());
+    if !lobby_timer.finished() {
         return;
     }
 
     info!("All players have connected!");
 
-    // Generate a random seed based on connected peer IDs
-    let own_id = socket.id().expect("Failed to get socket ID").0.as_u64_pair();
-    let mut seed = own_id.0 ^ own_id.1;
-    for peer in socket.connected_peers() {
-        let peer_id = peer.0.as_u64_pair();
-        seed ^= peer_id.0 ^ peer_id.1;
-    }
+    let (players, spectators) = peers.split_at(required_players);
+
+    // Generate a random seed based on the players' peer IDs, unless a fixed
+    // seed was passed on the command line
+    let seed = settings.seed.unwrap_or_else(|| {
+        let mut seed = 0;
+        for peer in players {
+            let peer_id = peer.0.as_u64_pair();
+            seed ^= peer_id.0 ^ peer_id.1;
+        }
+        seed
+    });
 
     commands.insert_resource(RandomSeed(seed));
 
-    let mut session_builder = ggrs::SessionBuilder::<GameConfig>::new()
+    let session_builder = ggrs::SessionBuilder::<GameConfig>::new()
         .with_num_players(required_players)
+        .with_fps(settings.tick_rate)
+        .expect("Tick rate was validated when parsing settings")
         .with_input_delay(2);
 
-    for (index, player) in connected_players.into_iter().enumerate() {
+    let communication_channel = socket.take_channel(GGRS_CHANNEL).unwrap();
+
+    commands.insert_resource(PeerHandles(
+        peers.iter().enumerate().map(|(handle, &peer)| (peer, handle)).collect(),
+    ));
+
+    if spectators.contains(&own_id) {
+        info!("Joining as a spectator");
+        let host = players[0];
+        let ggrs_session =
+            session_builder.start_spectator_session(host, communication_channel);
+
+        commands.insert_resource(LocalRole::Spectator);
+        commands.insert_resource(bevy_ggrs::Session::Spectator(ggrs_session));
+        next_state.set(AppState::InGame);
+        return;
+    }
+
+    let mut session_builder = session_builder;
+    for (handle, &peer) in players.iter().enumerate() {
+        let player_type = if peer == own_id {
+            ggrs::PlayerType::Local
+        } else {
+            ggrs::PlayerType::Remote(peer)
+        };
         session_builder = session_builder
-            .add_player(player, index)
+            .add_player(player_type, handle)
             .expect("Failed to add player to session");
     }
 
-    let communication_channel = socket.take_channel(0).unwrap();
+    // Only the host forwards confirmed inputs to spectators
+    if players[0] == own_id {
+        for (index, &spectator) in spectators.iter().enumerate() {
+            session_builder = session_builder
+                .add_player(ggrs::PlayerType::Spectator(spectator), required_players + index)
+                .expect("Failed to add spectator to session");
+        }
+    }
 
     let ggrs_session = session_builder
         .start_p2p_session(communication_channel)
         .expect("Failed to start P2P session");
 
+    commands.insert_resource(LocalRole::Player);
     commands.insert_resource(bevy_ggrs::Session::P2P(ggrs_session));
     next_state.set(AppState::InGame);
 }
+
+/// Stops the rollback simulation and disconnects once the match is over
+fn end_session(mut commands: Commands) {
+    commands.remove_resource::<bevy_ggrs::Session<GameConfig>>();
+    commands.remove_resource::<MatchboxSocket<MultipleChannels>>();
+    commands.remove_resource::<PeerHandles>();
+    commands.remove_resource::<LobbyStatus>();
+}
@@ -1,23 +1,64 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy_ggrs::{AddRollbackCommandExtension, PlayerInputs};
-use rand::{Rng, SeedableRng};
-use rand_xoshiro::Xoshiro256PlusPlus;
+use bevy_ggrs::{AddRollbackCommandExtension, PlayerInputs, RollbackFrameCount};
 
-use crate::barriers::create_world;
-use crate::input_handler::direction;
+use crate::barriers::{create_world, push_out_of_barrier, Barrier, HeldClick};
+use crate::events::{GrenadeExploded, KillCause, KillStreakReached, PendingEvents, PlayerKilled, RoundEnded};
+use crate::input_handler::{direction, is_meleeing};
+use crate::maps::ChosenMap;
 use crate::network_manager::*;
-use crate::projectile::Projectile;
-use crate::{GameConfig, GamePhase, WORLD_SIZE, GameTextures};
-use crate::utilities::PlayerScores;
+use crate::practice::PracticeRange;
+use crate::projectile::{Ammo, Projectile, Weapon};
+use crate::safe_zone::{SafeZone, SAFE_ZONE_DAMAGE};
+use crate::settings::{GameMode, GameSettings};
+use crate::{GameConfig, GamePhase, GameTextures};
+use crate::utilities::{wrap_position, FrameTimer, GameRng, KillStreaks, MatchProgress, PlayerLives, PlayerScores, PlayerStats};
 
+/// Default hitbox radii, tunable with `--player-radius` and `--projectile-radius`
 pub const PLAYER_RADIUS: f32 = 0.5;
 pub const PROJECTILE_RADIUS: f32 = 0.025;
+pub const PLAYER_MAX_HEALTH: u32 = 3;
+/// How far a hit pushes a player along the projectile's path
+pub const KNOCKBACK_DISTANCE: f32 = 1.0;
+/// Extra damage taken when a hit knocks a player into a barrier
+pub const WALL_SLAM_DAMAGE: u32 = 1;
+/// Distance from a grenade explosion within which players are hurt
+pub const GRENADE_BLAST_RADIUS: f32 = 2.5;
+pub const GRENADE_BLAST_DAMAGE: u32 = 2;
+/// Fraction of their speed players keep while on an opponent's slow tile
+pub const SLOW_TILE_SPEED_MULTIPLIER: f32 = 0.5;
+/// How long an incendiary hit keeps a player burning
+pub const BURN_SECONDS: u32 = 3;
+pub const BURN_DAMAGE_PER_SECOND: u32 = 1;
+/// How much faster players move during sudden death
+pub const SUDDEN_DEATH_SPEED_MULTIPLIER: f32 = 1.5;
+/// Kill streak that earns a burst of speed
+pub const SPEED_STREAK: u32 = 3;
+pub const STREAK_SPEED_MULTIPLIER: f32 = 1.3;
+pub const STREAK_SPEED_SECONDS: f32 = 5.0;
+/// Every multiple of this kill streak earns a point of health over the maximum
+pub const HEALTH_STREAK: u32 = 5;
+/// How far past the edge of the attacker a melee swing reaches
+pub const MELEE_RANGE: f32 = 1.0;
+/// Angle either side of the attacker's aim that a melee swing covers
+pub const MELEE_HALF_ARC_DEGREES: f32 = 60.0;
+pub const MELEE_DAMAGE: u32 = 1;
+pub const MELEE_KNOCKBACK_DISTANCE: f32 = 1.5;
+pub const MELEE_COOLDOWN_SECONDS: f32 = 0.6;
+/// Seconds spawn points are marked before players appear on them
+pub const SPAWN_TELEGRAPH_SECONDS: f32 = 1.0;
+/// Opacity of the marker shown where a player is about to appear
+const SPAWN_MARKER_ALPHA: f32 = 0.35;
 
 /// Registers the player module systems to the app
 pub(super) fn plugin(app: &mut App) {
     app.add_systems(
         OnEnter(GamePhase::ActiveRound),
         initialize_players.after(create_world),
+    )
+    .add_systems(
+        OnEnter(GamePhase::SuddenDeath),
+        (initialize_players.after(create_world), start_sudden_death).chain(),
     );
 }
 
@@ -29,35 +70,81 @@ pub struct Player {
     pub(crate) color: Color,
 }
 
-/// Component indicating if the player can attack
+/// Component tracking when the player can fire again
+#[derive(Component, Clone, Copy, Default)]
+pub struct CanAttack {
+    /// Frames until the weapon has cycled and can fire again
+    pub cooldown: FrameTimer,
+    /// Whether the fire button was held on the previous frame, so a new press can be told apart
+    pub trigger_held: bool,
+}
+
+/// Component tracking when the player can swing a melee attack again
+#[derive(Component, Clone, Copy, Default)]
+pub struct MeleeCooldown(pub FrameTimer);
+
+/// Component for storing how many more hits a player can take
+#[derive(Component, Clone, Copy, Hash)]
+pub struct Health(pub u32);
+
+/// Component for a player set on fire, taking damage every second
+#[derive(Component, Clone, Copy)]
+pub struct Burning {
+    /// Frames left until the fire goes out
+    pub frames: u32,
+    /// Damage dealt each second, before the damage multiplier
+    pub dps: u32,
+    /// Handle of the player who started the fire, credited with the kill
+    pub owner: usize,
+}
+
+/// Whether the player is standing on an opponent's slow tile, set by
+/// `handle_barrier_collisions` and applied on the next move
+#[derive(Component, Clone, Copy, Default)]
+pub struct Slowed(pub bool);
+
+/// A burst of speed earned with a kill streak, removed when its timer runs out
 #[derive(Component, Clone, Copy)]
-pub struct CanAttack(pub bool);
+pub struct StreakBoost(FrameTimer);
+
+/// Every `(killer, victim)` pair of the current frame, gathered from all the
+/// systems that can kill so `decide_round` sees them together
+#[derive(Resource, Clone, Default)]
+pub struct RoundKills(Vec<(usize, usize)>);
 
 /// Component for storing movement direction
 #[derive(Component, Clone, Copy)]
 pub struct MovementDirection(pub Vec2);
 
+/// Marks where a player will appear at the start of a round, so nobody is
+/// caught off guard by an opponent spawning next to them
+#[derive(Component, Clone, Copy)]
+pub struct SpawnMarker {
+    pub(crate) handle: usize,
+    initial_direction: Vec2,
+    /// Frames until the player appears
+    timer: FrameTimer,
+}
+
 /// Component representing a gun entity
 #[derive(Component)]
 pub struct Gun;
 
-/// Initializes players at the start of a new round
+/// Picks where every player spawns at the start of a new round and marks it
+///
+/// Players appear on their markers once `SPAWN_TELEGRAPH_SECONDS` have passed,
+/// see `materialize_players`. Everything random is drawn here, up front.
 fn initialize_players(
     mut commands: Commands,
-    existing_players: Query<(Entity, &Transform), With<Player>>,
+    existing_players: Query<Entity, Or<(With<Player>, With<SpawnMarker>)>>,
     existing_projectiles: Query<Entity, With<Projectile>>,
-    random_seed: Res<RandomSeed>,
-    player_scores: Res<PlayerScores>,
-    game_textures: Res<GameTextures>,
+    mut rng: ResMut<GameRng>,
+    settings: Res<GameSettings>,
+    chosen_map: ChosenMap,
+    lives: Res<PlayerLives>,
 ) {
-    // Sum up the x positions of all existing players
-    let total_x: f32 = existing_players
-        .iter()
-        .map(|(_, transform)| transform.translation.x)
-        .sum();
-
-    // Despawn all existing players
-    for (entity, _) in existing_players.iter() {
+    // Despawn all existing players and spawn markers
+    for entity in existing_players.iter() {
         commands.entity(entity).despawn_recursive();
     }
 
@@ -66,63 +153,148 @@ fn initialize_players(
         commands.entity(entity).despawn_recursive();
     }
 
-    // Initialize RNG with a combined seed
-    let seed_value = (1234u64 + total_x as u64 + player_scores.get(0) +
-        player_scores.get(1)) ^ **random_seed;
-    let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed_value);
+    // Use the map's spawn points if it has them, so neither player starts
+    // with an advantage, otherwise generate random positions
+    let player_positions = chosen_map
+        .get()
+        .and_then(|map| map.spawn_points())
+        .unwrap_or_else(|| {
+            (0..NUM_PLAYERS).map(|_| rng.position()).collect()
+        });
 
-    let half_world_size = WORLD_SIZE as f32 * 0.5;
+    // Mark each spawn point, leaving out anyone eliminated
+    for i in 0..NUM_PLAYERS {
+        if lives.is_eliminated(i) {
+            continue;
+        }
 
-    // Generate random positions for the players
-    let mut player_positions = Vec::with_capacity(NUM_PLAYERS);
-    for _ in 0..NUM_PLAYERS {
-        player_positions.push(Vec2::new(
-            rng.gen_range(-half_world_size..half_world_size),
-            rng.gen_range(-half_world_size..half_world_size),
-        ));
+        let initial_direction = rng.direction();
+        let timer = FrameTimer::from_seconds(SPAWN_TELEGRAPH_SECONDS, settings.tick_rate);
+        spawn_marker(&mut commands, i, player_positions[i], initial_direction, timer, &settings);
     }
+}
 
-    // Spawn in players
-    for i in 0..NUM_PLAYERS {
-        let color = Color::srgb(
-            rng.gen_range(0.0..1.0),
-            rng.gen_range(0.0..1.0),
-            rng.gen_range(0.0..1.0),
-        );
+/// Marks where a player will appear once `timer` runs out
+pub(crate) fn spawn_marker(
+    commands: &mut Commands,
+    handle: usize,
+    position: Vec2,
+    initial_direction: Vec2,
+    timer: FrameTimer,
+    settings: &GameSettings,
+) {
+    let color = player_color(handle, &settings.player_colors);
+
+    commands
+        .spawn((
+            SpawnMarker {
+                handle,
+                initial_direction,
+                timer,
+            },
+            SpriteBundle {
+                transform: Transform::from_translation(position.extend(50.0)),
+                sprite: Sprite {
+                    color: color.with_alpha(SPAWN_MARKER_ALPHA),
+                    custom_size: Some(Vec2::new(1.0, 1.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+        .add_rollback();
+}
 
-        let initial_direction = Vec2::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-        ).normalize_or_zero();
+/// Replaces each spawn marker with its player once the marker's countdown runs out
+pub fn materialize_players(
+    mut commands: Commands,
+    mut markers: Query<(Entity, &Transform, &mut SpawnMarker)>,
+    game_textures: Res<GameTextures>,
+    images: Res<Assets<Image>>,
+    settings: Res<GameSettings>,
+    progress: Res<MatchProgress>,
+) {
+    // Scale the gun art so it's the configured length whatever its resolution
+    let gun_width = images
+        .get(&game_textures.gun_image)
+        .map_or(1.0, |image| image.width() as f32);
+    let gun_scale = settings.gun_length / gun_width;
+
+    for (entity, transform, mut marker) in &mut markers {
+        if !marker.timer.tick() {
+            continue;
+        }
+        commands.entity(entity).despawn_recursive();
+
+        // Sudden death is faster and every hit kills
+        let (speed, health) = if progress.sudden_death {
+            (settings.base_player_speed * SUDDEN_DEATH_SPEED_MULTIPLIER, 1)
+        } else {
+            (settings.base_player_speed, PLAYER_MAX_HEALTH)
+        };
 
         create_player(
             &mut commands,
-            player_positions[i],
-            i,
-            color,
-            initial_direction,
+            transform.translation.xy(),
+            marker.handle,
+            speed,
+            health,
+            player_color(marker.handle, &settings.player_colors),
+            settings.starting_weapon,
+            marker.initial_direction,
             game_textures.gun_image.clone(),
+            settings.gun_offset,
+            gun_scale,
         );
     }
 }
 
+/// Players spawning from now on are faster and die in one hit, see `materialize_players`
+fn start_sudden_death(mut progress: ResMut<MatchProgress>) {
+    progress.sudden_death = true;
+}
+
+/// Picks a player's color from the palette by handle, so it stays the same every round
+///
+/// Handles beyond the palette get hues spaced by the golden angle, which keeps
+/// them deterministic and well apart from each other.
+pub fn player_color(handle: usize, palette: &[Color]) -> Color {
+    if let Some(&color) = palette.get(handle) {
+        return color;
+    }
+
+    let hue = (handle as f32 * 137.508) % 360.0;
+    Color::hsl(hue, 0.8, 0.5)
+}
+
 /// Helper function to create a player entity
 fn create_player(
     commands: &mut Commands,
     position: Vec2,
     handle: usize,
+    speed: f32,
+    health: u32,
     color: Color,
+    weapon: Weapon,
     initial_direction: Vec2,
     gun_image: Handle<Image>,
+    gun_offset: f32,
+    gun_scale: f32,
 ) {
     let player_entity = commands
         .spawn((
             Player {
-                speed: 10.0,
+                speed,
                 handle,
                 color
             },
-            CanAttack(true),
+            CanAttack::default(),
+            MeleeCooldown::default(),
+            Health(health),
+            weapon,
+            Ammo::full(weapon),
+            Slowed::default(),
+            HeldClick::default(),
             MovementDirection(initial_direction),
             SpriteBundle {
                 transform: Transform::from_translation(position.extend(100.0)),
@@ -144,9 +316,10 @@ fn create_player(
             SpriteBundle {
                 texture: gun_image,
                 transform: Transform {
-                    translation: Vec3::new(0.5, 0.0, 1.0), // Offset to position in front of the player
+                    // Offset to position in front of the player
+                    translation: (initial_direction * gun_offset).extend(1.0),
                     rotation: Quat::from_rotation_z(initial_direction.y.atan2(initial_direction.x)),
-                    scale: Vec3::new(0.005, 0.005, 1.0),
+                    scale: Vec3::new(gun_scale, gun_scale, 1.0),
                 },
                 ..Default::default()
             },
@@ -156,12 +329,21 @@ fn create_player(
 
 /// Moves players based on their input and updates their position
 pub fn move_players(
-    mut player_query: Query<(&mut Transform, &mut MovementDirection, &Player), With<Player>>,
+    mut player_query: Query<
+        (&mut Transform, &mut MovementDirection, &Player, &Slowed, Option<&StreakBoost>, Option<&Children>),
+        With<Player>,
+    >,
     mut gun_query: Query<&mut Transform, (With<Gun>, Without<Player>)>,
     inputs: Res<PlayerInputs<GameConfig>>,
-    time: Res<Time>,
+    settings: Res<GameSettings>,
 ) {
-    for (mut transform, mut movement_direction, player) in &mut player_query {
+    for (mut transform, mut movement_direction, player, slowed, boost, children) in &mut player_query {
+        // Knockback can also carry a player over the edge, so wrap before moving too
+        if settings.wrap_edges {
+            let wrapped = wrap_position(transform.translation.xy());
+            transform.translation = wrapped.extend(transform.translation.z);
+        }
+
         let (input_bits, _) = inputs[player.handle];
 
         let direction_vector = direction(input_bits);
@@ -170,69 +352,630 @@ pub fn move_players(
             continue;
         }
 
-        movement_direction.0 = direction_vector;
+        // Analog input can be shorter than a unit vector, but facing is always normalized
+        let facing = direction_vector.normalize();
+        movement_direction.0 = facing;
 
-        let movement_delta = direction_vector * player.speed * time.delta_seconds();
+        let mut speed = if slowed.0 {
+            player.speed * SLOW_TILE_SPEED_MULTIPLIER
+        } else {
+            player.speed
+        };
+        if boost.is_some() {
+            speed *= STREAK_SPEED_MULTIPLIER;
+        }
+        let movement_delta = direction_vector * speed * settings.frame_delta();
 
-        let current_position = transform.translation.xy();
-        let boundary_limit = Vec2::splat(WORLD_SIZE as f32 * 0.5 - 0.5);
-        let new_position = (current_position + movement_delta)
-            .clamp(-boundary_limit, boundary_limit);
+        // The walls around the map keep players in, see `handle_barrier_collisions`,
+        // unless they're left out to wrap players across the edges
+        let mut new_position = transform.translation.xy() + movement_delta;
+        if settings.wrap_edges {
+            new_position = wrap_position(new_position);
+        }
 
         transform.translation.x = new_position.x;
         transform.translation.y = new_position.y;
 
-        // Update gun position and rotation
-        for mut gun_transform in &mut gun_query {
+        // Update the position and rotation of this player's own gun
+        for &child in children.into_iter().flatten() {
+            let Ok(mut gun_transform) = gun_query.get_mut(child) else {
+                continue;
+            };
             gun_transform.translation = Vec3::new(
-                direction_vector.x * 0.5,
-                direction_vector.y * 0.5,
+                facing.x * settings.gun_offset,
+                facing.y * settings.gun_offset,
                 gun_transform.translation.z,
             );
             gun_transform.rotation =
-                Quat::from_rotation_z(direction_vector.y.atan2(direction_vector.x));
+                Quat::from_rotation_z(facing.y.atan2(facing.x));
+        }
+    }
+}
+
+/// Everything needed to record deaths and decide when a round is over
+#[derive(SystemParam)]
+pub struct RoundOutcome<'w> {
+    next_state: ResMut<'w, NextState<GamePhase>>,
+    player_scores: ResMut<'w, PlayerScores>,
+    player_stats: ResMut<'w, PlayerStats>,
+    pending_events: ResMut<'w, PendingEvents>,
+    kills: ResMut<'w, RoundKills>,
+    frame: Res<'w, RollbackFrameCount>,
+    phase: Res<'w, State<GamePhase>>,
+    lives: ResMut<'w, PlayerLives>,
+    streaks: ResMut<'w, KillStreaks>,
+    progress: Res<'w, MatchProgress>,
+    settings: Res<'w, GameSettings>,
+    practice: Option<Res<'w, PracticeRange>>,
+}
+
+impl RoundOutcome<'_> {
+    /// Removes a player whose health ran out and credits their killer
+    fn kill(
+        &mut self,
+        commands: &mut Commands,
+        entity: Entity,
+        victim: &Player,
+        killer: usize,
+        cause: KillCause,
+        position: Vec2,
+    ) {
+        commands.entity(entity).despawn_recursive();
+        self.kills.0.push((killer, victim.handle));
+        self.player_stats.record_kill(killer, victim.handle);
+        if let Some(streak) = self.streaks.record_kill(killer, victim.handle) {
+            if streak_reward(streak).is_some() {
+                self.pending_events.push(&self.frame, KillStreakReached { player: killer, streak });
+            }
+        }
+        if self.settings.game_mode == GameMode::Elimination {
+            self.lives.lose_life(victim.handle, self.progress.rounds_played);
+        }
+        self.pending_events.push(
+            &self.frame,
+            PlayerKilled {
+                killer,
+                victim: victim.handle,
+                cause,
+                position,
+                victim_color: victim.color,
+            },
+        );
+    }
+
+    /// Records a player-placed barrier being shot down
+    ///
+    /// In a demolition match, destroying an opponent's barrier scores a point
+    /// and reaching the barrier target ends the round straight away. Sudden
+    /// death is still decided by the first kill.
+    pub fn destroy_barrier(&mut self, destroyer: usize, placer: usize) {
+        if destroyer == placer {
+            return;
+        }
+        self.player_stats.record_barrier_destroyed(destroyer);
+
+        let scores_barriers = self.settings.game_mode == GameMode::Demolition && self.practice.is_none();
+        if !scores_barriers || *self.phase.get() != GamePhase::ActiveRound {
+            return;
+        }
+        let score = self.player_scores.get(destroyer) + 1;
+        self.player_scores.set(destroyer, score);
+
+        // Someone else may already have ended the round this frame
+        let round_ending = matches!(*self.next_state, NextState::Pending(_));
+        if score >= self.settings.barrier_target as u64 && !round_ending {
+            self.next_state.set(GamePhase::RoundOver);
+            self.pending_events.push(&self.frame, RoundEnded { winner: Some(destroyer) });
+        }
+    }
+
+    /// Ends the round once at most one player is left standing, or on the
+    /// first kill during sudden death
+    ///
+    /// Runs once per frame after every system that can kill, with this
+    /// frame's victims already gone from `survivors`.
+    fn end_round_if_decided(&mut self, mut survivors: impl Iterator<Item = usize>) {
+        let kills = std::mem::take(&mut self.kills.0);
+        // Rounds on the practice range go on forever, with players respawning instead
+        if self.practice.is_some() {
+            return;
+        }
+        // A demolition win may already have ended the round this frame
+        if matches!(*self.next_state, NextState::Pending(_)) {
+            return;
+        }
+        let Some(&(first_killer, _)) = kills.first() else {
+            return;
+        };
+        let is_victim = |handle: &usize| kills.iter().any(|&(_, victim)| victim == *handle);
+
+        let winner = if *self.phase.get() == GamePhase::SuddenDeath {
+            // A killer who died on the same frame doesn't win, so it's another draw
+            Some(first_killer).filter(|killer| !is_victim(killer))
+        } else {
+            let winner = survivors.next();
+            if survivors.next().is_some() {
+                return;
+            }
+            winner
+        };
+
+        // Round wins don't score in demolition, except for breaking a tie
+        let scores_round = self.settings.game_mode != GameMode::Demolition
+            || *self.phase.get() == GamePhase::SuddenDeath;
+        if let Some(winner) = winner.filter(|_| scores_round) {
+            let score = self.player_scores.get(winner);
+            self.player_scores.set(winner, score + 1);
         }
+
+        self.next_state.set(GamePhase::RoundOver);
+        self.pending_events.push(&self.frame, RoundEnded { winner });
     }
 }
 
+/// A world holding the match resources `RoundOutcome` needs, for running
+/// single systems in tests without a session
+#[cfg(test)]
+pub(crate) fn round_outcome_world(settings: GameSettings) -> World {
+    let mut world = World::new();
+    world.insert_resource(PlayerLives::new(settings.lives));
+    world.insert_resource(settings);
+    world.insert_resource(State::new(GamePhase::ActiveRound));
+    world.init_resource::<NextState<GamePhase>>();
+    world.insert_resource(PlayerScores::new());
+    world.insert_resource(PlayerStats::new());
+    world.insert_resource(KillStreaks::new());
+    world.init_resource::<PendingEvents>();
+    world.init_resource::<RoundKills>();
+    world.init_resource::<RollbackFrameCount>();
+    world.init_resource::<MatchProgress>();
+    world
+}
+
 /// Checks for collisions between players and projectiles
+///
+/// Each hit deals damage and knocks the player back along the projectile's
+/// path. With wall slams enabled, a hit that knocks the player into a barrier
+/// deals bonus damage; this is detected by resolving the knockback against the
+/// barriers straight away and checking whether that moved the player.
+/// Incendiary projectiles set the player on fire instead of dealing damage
+/// or knocking them back.
+/// Grenades flying overhead miss, and low ones explode on contact instead.
 pub fn check_player_collisions(
     mut commands: Commands,
-    player_query: Query<(Entity, &Transform, &Player), (With<Player>, Without<Projectile>)>,
-    projectile_query: Query<&Transform, With<Projectile>>,
-    mut next_state: ResMut<NextState<GamePhase>>,
-    mut playerscores: ResMut<PlayerScores>
+    mut player_query: Query<(Entity, &mut Transform, &Player, &mut Health), (With<Player>, Without<Projectile>)>,
+    mut projectile_query: Query<(Entity, &Transform, &mut Projectile, &MovementDirection), Without<Player>>,
+    barriers: Query<(&Barrier, &Transform, &Sprite), (Without<Player>, Without<Projectile>)>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
 ) {
-    for (player_entity, player_transform, player) in &player_query {
-        let player_pos = player_transform.translation.xy();
-        for projectile_transform in &projectile_query {
+    // A projectile is despawned on its first hit, but despawns are deferred
+    let mut spent_projectiles = Vec::new();
+
+    for (player_entity, mut player_transform, player, mut health) in &mut player_query {
+        for (projectile_entity, projectile_transform, mut projectile, projectile_direction) in &mut projectile_query {
+            if spent_projectiles.contains(&projectile_entity) || projectile.is_airborne() {
+                continue;
+            }
+
+            let player_pos = player_transform.translation.xy();
             let projectile_pos = projectile_transform.translation.xy();
-            if is_colliding(player_pos, projectile_pos, PLAYER_RADIUS, PROJECTILE_RADIUS) {
-                commands.entity(player_entity).despawn_recursive();
-                println!("Player killed!");
-                if NUM_PLAYERS > 2 {
-                    if player_query.iter().count() == 1 {
-                        next_state.set(GamePhase::RoundOver);
-                        break;
-                    }
-                } else {
-                    next_state.set(GamePhase::RoundOver);
-                    if player.handle == 0 {
-                        let score = playerscores.get(1);
-                        playerscores.set(1, score + 1);
-                    } else {
-                        let score = playerscores.get(0);
-                        playerscores.set(0, score + 1);
-                    }
-                    break;
-                }
+            if !is_colliding(player_pos, projectile_pos, settings.player_radius, settings.projectile_radius) {
+                continue;
+            }
+
+            // The explosion does the damage, see `explode_grenades`
+            if projectile.is_explosive() {
+                projectile.detonate();
+                spent_projectiles.push(projectile_entity);
+                continue;
+            }
+
+            commands.entity(projectile_entity).despawn_recursive();
+            spent_projectiles.push(projectile_entity);
+
+            // The fire does the damage, without knocking the player around
+            if projectile.ignites {
+                commands.entity(player_entity).insert(Burning {
+                    frames: BURN_SECONDS * settings.tick_rate as u32,
+                    dps: BURN_DAMAGE_PER_SECOND,
+                    owner: projectile.owner,
+                });
+                continue;
+            }
+
+            let knocked_pos = player_pos + projectile_direction.0 * KNOCKBACK_DISTANCE;
+            let resolved_pos = resolve_knockback(knocked_pos, &barriers, &settings);
+            player_transform.translation.x = resolved_pos.x;
+            player_transform.translation.y = resolved_pos.y;
+
+            let mut damage = projectile.damage;
+            if settings.wall_slam && resolved_pos != knocked_pos {
+                damage += WALL_SLAM_DAMAGE;
+            }
+            health.0 = health.0.saturating_sub(damage);
+
+            if health.0 == 0 {
+                outcome.kill(&mut commands, player_entity, player, projectile.owner, KillCause::Shot, resolved_pos);
+                break;
+            }
+        }
+    }
+}
+
+/// Swings every melee attack started this frame, hurting and knocking back
+/// opponents within reach in front of the attacker
+///
+/// Hits are worked out from where everyone stood before any knockback and
+/// applied in order of attacker, then victim handle, so every peer resolves
+/// swings landing on the same frame the same way.
+pub fn melee_attacks(
+    mut commands: Commands,
+    mut players: Query<(Entity, &mut Transform, &Player, &mut Health, &MovementDirection, &mut MeleeCooldown)>,
+    barriers: Query<(&Barrier, &Transform, &Sprite), (Without<Player>, Without<Projectile>)>,
+    inputs: Res<PlayerInputs<GameConfig>>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
+) {
+    let mut swings = Vec::new();
+    for (_, transform, player, _, facing, mut cooldown) in &mut players {
+        cooldown.0.tick();
+
+        let (input, _) = inputs[player.handle];
+        if !is_meleeing(input) || !cooldown.0.finished() {
+            continue;
+        }
+        cooldown.0 = FrameTimer::from_seconds(MELEE_COOLDOWN_SECONDS, settings.tick_rate);
+        swings.push((player.handle, transform.translation.xy(), facing.0));
+    }
+    if swings.is_empty() {
+        return;
+    }
+    swings.sort_by_key(|&(handle, _, _)| handle);
+
+    let mut targets: Vec<(usize, Entity, Vec2)> = players
+        .iter()
+        .map(|(entity, transform, player, ..)| (player.handle, entity, transform.translation.xy()))
+        .collect();
+    targets.sort_by_key(|&(handle, _, _)| handle);
+
+    let reach = settings.player_radius * 2.0 + MELEE_RANGE;
+    let min_alignment = MELEE_HALF_ARC_DEGREES.to_radians().cos();
+
+    // Despawns are deferred, so players killed by an earlier swing are still in `targets`
+    let mut dead = Vec::new();
+    for &(attacker, origin, facing) in &swings {
+        for &(victim, entity, position) in &targets {
+            if victim == attacker || dead.contains(&victim) {
+                continue;
+            }
+
+            // Players standing on top of each other are always in the arc
+            let offset = position - origin;
+            let in_arc = offset
+                .try_normalize()
+                .map_or(true, |direction| facing.dot(direction) >= min_alignment);
+            if offset.length() > reach || !in_arc {
+                continue;
+            }
+
+            let Ok((_, mut transform, player, mut health, _, _)) = players.get_mut(entity) else {
+                continue;
+            };
+
+            let push = offset.try_normalize().unwrap_or(facing);
+            let knocked_pos = transform.translation.xy() + push * MELEE_KNOCKBACK_DISTANCE;
+            let resolved_pos = resolve_knockback(knocked_pos, &barriers, &settings);
+            transform.translation.x = resolved_pos.x;
+            transform.translation.y = resolved_pos.y;
+
+            health.0 = health.0.saturating_sub(MELEE_DAMAGE * settings.damage_multiplier);
+            if health.0 == 0 {
+                outcome.kill(&mut commands, entity, player, attacker, KillCause::Melee, resolved_pos);
+                dead.push(victim);
             }
         }
     }
 }
 
+/// Where a player knocked to `position` ends up once pushed back out of any solid barrier
+fn resolve_knockback(
+    position: Vec2,
+    barriers: &Query<(&Barrier, &Transform, &Sprite), (Without<Player>, Without<Projectile>)>,
+    settings: &GameSettings,
+) -> Vec2 {
+    barriers
+        .iter()
+        .filter(|(barrier, _, _)| barrier.is_solid())
+        .fold(position, |pos, (_, barrier_transform, barrier_sprite)| {
+            let barrier_size = barrier_sprite.custom_size.expect("Barrier has no size");
+            push_out_of_barrier(pos, settings.player_radius, barrier_transform.translation.xy(), barrier_size)
+        })
+}
+
+/// Blows up grenades whose fuse ran out or that hit something this frame,
+/// damaging every player in range, including the thrower
+pub fn explode_grenades(
+    mut commands: Commands,
+    grenades: Query<(Entity, &Transform, &Projectile), Without<Player>>,
+    mut players: Query<(Entity, &Transform, &Player, &mut Health)>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
+) {
+    let explosions: Vec<(Vec2, usize)> = grenades
+        .iter()
+        .filter(|(_, _, projectile)| projectile.should_explode())
+        .map(|(entity, transform, projectile)| {
+            commands.entity(entity).despawn_recursive();
+            (transform.translation.xy(), projectile.owner)
+        })
+        .collect();
+    if explosions.is_empty() {
+        return;
+    }
+
+    for &(position, owner) in &explosions {
+        outcome.pending_events.push(&outcome.frame, GrenadeExploded { owner, position });
+    }
+
+    for (entity, transform, player, mut health) in &mut players {
+        let player_pos = transform.translation.xy();
+        for &(position, owner) in &explosions {
+            if player_pos.distance(position) > GRENADE_BLAST_RADIUS {
+                continue;
+            }
+
+            health.0 = health.0.saturating_sub(GRENADE_BLAST_DAMAGE * settings.damage_multiplier);
+            if health.0 == 0 {
+                outcome.kill(&mut commands, entity, player, owner, KillCause::Grenade, player_pos);
+                break;
+            }
+        }
+    }
+}
+
+/// Deals burn damage once per second until the fire goes out
+pub fn tick_burning(
+    mut commands: Commands,
+    mut burning_players: Query<(Entity, &Transform, &Player, &mut Health, &mut Burning)>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
+) {
+    for (entity, transform, player, mut health, mut burning) in &mut burning_players {
+        burning.frames = burning.frames.saturating_sub(1);
+        if burning.frames % settings.tick_rate as u32 == 0 {
+            health.0 = health.0.saturating_sub(burning.dps * settings.damage_multiplier);
+        }
+
+        if health.0 == 0 {
+            outcome.kill(&mut commands, entity, player, burning.owner, KillCause::Burn, transform.translation.xy());
+        } else if burning.frames == 0 {
+            commands.entity(entity).remove::<Burning>();
+        }
+    }
+}
+
+/// Hurts every player outside the safe zone once per damage interval
+///
+/// Dying to the zone counts as a self-inflicted death.
+pub fn damage_outside_safe_zone(
+    mut commands: Commands,
+    mut players: Query<(Entity, &Transform, &Player, &mut Health)>,
+    mut zone: ResMut<SafeZone>,
+    settings: Res<GameSettings>,
+    mut outcome: RoundOutcome,
+) {
+    if !zone.tick_damage(&settings) {
+        return;
+    }
+
+    for (entity, transform, player, mut health) in &mut players {
+        let position = transform.translation.xy();
+        if zone.contains(position) {
+            continue;
+        }
+
+        health.0 = health.0.saturating_sub(SAFE_ZONE_DAMAGE);
+        if health.0 == 0 {
+            outcome.kill(&mut commands, entity, player, player.handle, KillCause::SafeZone, position);
+        }
+    }
+}
+
+/// Ends the round if any of this frame's kills decided it
+///
+/// Victims are despawned by the time this runs, so `players` only sees survivors.
+pub fn decide_round(players: Query<&Player>, mut outcome: RoundOutcome) {
+    outcome.end_round_if_decided(players.iter().map(|player| player.handle));
+}
+
+/// What a player earns for reaching a kill streak
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreakReward {
+    Speed,
+    ExtraHealth,
+}
+
+/// The reward for reaching `streak` kills in a row, if that streak earns one
+pub fn streak_reward(streak: u32) -> Option<StreakReward> {
+    match streak {
+        SPEED_STREAK => Some(StreakReward::Speed),
+        _ if streak % HEALTH_STREAK == 0 => Some(StreakReward::ExtraHealth),
+        _ => None,
+    }
+}
+
+/// Grants the rewards for kill streaks reached this frame and runs down speed boosts
+///
+/// Rewards are claimed from `KillStreaks` rather than granted where the kill
+/// happens, since the killer's entity isn't at hand there.
+pub fn grant_streak_rewards(
+    mut commands: Commands,
+    mut players: Query<(Entity, &Player, &mut Health, Option<&mut StreakBoost>)>,
+    mut streaks: ResMut<KillStreaks>,
+    settings: Res<GameSettings>,
+) {
+    for (entity, player, mut health, boost) in &mut players {
+        if let Some(mut boost) = boost {
+            if boost.0.tick() {
+                commands.entity(entity).remove::<StreakBoost>();
+            }
+        }
+
+        for streak in streaks.claim_rewards(player.handle) {
+            match streak_reward(streak) {
+                Some(StreakReward::Speed) => {
+                    commands.entity(entity).insert(StreakBoost(FrameTimer::from_seconds(
+                        STREAK_SPEED_SECONDS,
+                        settings.tick_rate,
+                    )));
+                }
+                Some(StreakReward::ExtraHealth) => health.0 += 1,
+                None => {}
+            }
+        }
+    }
+}
 
 /// Determines if two circles are colliding
 fn is_colliding(pos1: Vec2, pos2: Vec2, radius1: f32, radius2: f32) -> bool {
     Vec2::distance(pos1, pos2) < radius1 + radius2
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+    use crate::determinism::SimulationHarness;
+    use crate::input_handler::{INPUT_READY, INPUT_RIGHT};
+
+    fn spawn_player(world: &mut World, handle: usize, health: u32) -> Entity {
+        world
+            .spawn((
+                Player {
+                    speed: 1.0,
+                    handle,
+                    color: Color::WHITE,
+                },
+                Health(health),
+                Transform::default(),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn burning_lasts_its_frames_then_goes_out() {
+        let settings = GameSettings {
+            damage_multiplier: 2,
+            ..default()
+        };
+        let tick_rate = settings.tick_rate as u32;
+        let mut world = round_outcome_world(settings);
+        let player = spawn_player(&mut world, 0, 10);
+        world.entity_mut(player).insert(Burning {
+            frames: 2 * tick_rate,
+            dps: 1,
+            owner: 1,
+        });
+
+        for _ in 0..2 * tick_rate - 1 {
+            world.run_system_once(tick_burning);
+        }
+        assert!(world.get::<Burning>(player).is_some());
+        assert_eq!(world.get::<Health>(player).unwrap().0, 8);
+
+        world.run_system_once(tick_burning);
+        assert!(world.get::<Burning>(player).is_none());
+        assert_eq!(world.get::<Health>(player).unwrap().0, 6);
+
+        // Once out, the fire does no more damage
+        world.run_system_once(tick_burning);
+        assert_eq!(world.get::<Health>(player).unwrap().0, 6);
+    }
+
+    #[test]
+    fn incendiary_hits_ignite_without_knockback() {
+        let settings = GameSettings {
+            wall_slam: true,
+            ..default()
+        };
+        let mut world = round_outcome_world(settings);
+        let player = spawn_player(&mut world, 0, 3);
+        world.spawn((
+            Projectile {
+                owner: 1,
+                damage: Weapon::Incendiary.damage(),
+                ignites: true,
+                piercing: 0,
+                velocity: Vec2::X,
+                height: 0.0,
+                vertical_speed: 0.0,
+                gravity: 0.0,
+                fuse: None,
+                wraps_left: 0,
+            },
+            MovementDirection(Vec2::X),
+            Transform::default(),
+        ));
+
+        world.run_system_once(check_player_collisions);
+        assert!(world.get::<Burning>(player).is_some());
+        assert_eq!(world.get::<Transform>(player).unwrap().translation, Vec3::ZERO);
+        assert_eq!(world.get::<Health>(player).unwrap().0, 3);
+    }
+
+    fn walk_right(_frame: u32) -> u64 {
+        INPUT_READY | INPUT_RIGHT
+    }
+
+    #[test]
+    fn each_gun_faces_its_own_player_direction() {
+        let settings = GameSettings::default();
+        let step = Duration::from_secs_f64(0.5 / settings.tick_rate as f64);
+        let mut harness = SimulationHarness::new(settings, walk_right, step);
+        harness.run_until_frame(120).unwrap();
+
+        let world = harness.world_mut();
+        let players: Vec<(usize, Vec2, Vec<Entity>)> = world
+            .query::<(&Player, &MovementDirection, &Children)>()
+            .iter(world)
+            .map(|(player, facing, children)| (player.handle, facing.0, children.to_vec()))
+            .collect();
+        assert!(players.len() > 1);
+
+        for (handle, facing, children) in players {
+            let gun = children
+                .iter()
+                .filter(|&&child| world.get::<Gun>(child).is_some())
+                .find_map(|&child| world.get::<Transform>(child))
+                .expect("Player has no gun");
+            let expected = Quat::from_rotation_z(facing.y.atan2(facing.x));
+            assert!(
+                gun.rotation.abs_diff_eq(expected, 1e-5),
+                "player {handle}'s gun doesn't face {facing}"
+            );
+        }
+    }
+
+    #[test]
+    fn streaks_earn_a_speed_boost_then_extra_health() {
+        let mut world = round_outcome_world(GameSettings::default());
+        let player = spawn_player(&mut world, 0, 3);
+
+        let kill_and_reward = |world: &mut World| {
+            world.resource_mut::<KillStreaks>().record_kill(0, 1);
+            world.run_system_once(grant_streak_rewards);
+        };
+        for _ in 0..SPEED_STREAK {
+            kill_and_reward(&mut world);
+        }
+        assert!(world.get::<StreakBoost>(player).is_some());
+        assert_eq!(world.get::<Health>(player).unwrap().0, 3);
+
+        for _ in SPEED_STREAK..HEALTH_STREAK {
+            kill_and_reward(&mut world);
+        }
+        assert_eq!(world.get::<Health>(player).unwrap().0, 4);
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use bevy_ggrs::{GgrsSchedule, LocalInputs, ReadInputs};
+use crate::{AppState, GameConfig, GamePhase};
+use crate::barriers::Barrier;
+use crate::input_handler::{collect_player_inputs, encode_analog, INPUT_READY};
+use crate::network_manager::{LocalRole, Opponents, RandomSeed, NUM_PLAYERS};
+use crate::player_module::{grant_streak_rewards, spawn_marker, Player, SpawnMarker};
+use crate::settings::GameSettings;
+use crate::utilities::{FrameTimer, GameRng};
+
+/// Seconds a moving dummy strafes in one direction before turning back
+const DUMMY_STRAFE_SECONDS: f32 = 1.5;
+/// Key that leaves the practice range for the results screen
+const LEAVE_KEY: KeyCode = KeyCode::Escape;
+/// Positions drawn for a respawn before settling for the last one, so a
+/// crowded map can't stall the frame
+const RESPAWN_ATTEMPTS: u32 = 32;
+/// Closest to anyone alive a player can respawn
+const RESPAWN_CLEARANCE: f32 = 4.0;
+
+/// A target dummy standing in for an opponent on the practice range
+#[derive(Clone, Copy)]
+struct Dummy {
+    handle: usize,
+    /// Which way the dummy is strafing, 1 for right and -1 for left
+    heading: f32,
+    /// Frames until the dummy turns around
+    turn_timer: FrameTimer,
+}
+
+/// The single-player sandbox, where every player but the first is a dummy
+///
+/// Nobody ever wins a round on the range, and anyone who dies comes straight
+/// back, see `respawn_practice_players`. Like bot inputs, dummy inputs are
+/// generated outside the rollback simulation.
+#[derive(Resource)]
+pub struct PracticeRange {
+    dummies: Vec<Dummy>,
+}
+
+impl PracticeRange {
+    pub fn contains(&self, handle: usize) -> bool {
+        self.dummies.iter().any(|dummy| dummy.handle == handle)
+    }
+}
+
+/// Registers the practice range systems to the app
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(AppState::Connecting),
+        start_practice_session.run_if(resource_equals(Opponents::Practice)),
+    )
+    .add_systems(
+        ReadInputs,
+        collect_dummy_inputs
+            .after(collect_player_inputs)
+            .run_if(resource_exists::<PracticeRange>),
+    )
+    .add_systems(
+        GgrsSchedule,
+        respawn_practice_players
+            .after(bevy_roll_safe::apply_state_transition::<GamePhase>)
+            .after(grant_streak_rewards)
+            .run_if(in_state(GamePhase::ActiveRound).or_else(in_state(GamePhase::SuddenDeath)))
+            .run_if(resource_exists::<PracticeRange>),
+    )
+    .add_systems(OnExit(AppState::GameOver), remove_practice_range);
+}
+
+/// Starts a local session with the first player controlled here and dummies for the rest
+fn start_practice_session(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<AppState>>,
+    settings: Res<GameSettings>,
+) {
+    commands.insert_resource(RandomSeed(settings.seed.unwrap_or_else(rand::random)));
+
+    let mut session_builder = ggrs::SessionBuilder::<GameConfig>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_fps(settings.tick_rate)
+        .expect("Tick rate was validated when parsing settings")
+        .with_check_distance(0);
+    for handle in 0..NUM_PLAYERS {
+        session_builder = session_builder
+            .add_player(ggrs::PlayerType::Local, handle)
+            .expect("Failed to add player to session");
+    }
+    let ggrs_session = session_builder
+        .start_synctest_session()
+        .expect("Failed to start practice session");
+
+    commands.insert_resource(PracticeRange {
+        dummies: (1..NUM_PLAYERS)
+            .map(|handle| Dummy {
+                handle,
+                heading: 1.0,
+                turn_timer: FrameTimer::from_seconds(DUMMY_STRAFE_SECONDS, settings.tick_rate),
+            })
+            .collect(),
+    });
+    commands.insert_resource(LocalRole::Player);
+    commands.insert_resource(bevy_ggrs::Session::SyncTest(ggrs_session));
+    next_state.set(AppState::InGame);
+}
+
+/// Keeps dummies ready for the round and, unless they're told to stand still,
+/// strafing from side to side
+fn collect_dummy_inputs(
+    mut practice: ResMut<PracticeRange>,
+    mut local_inputs: ResMut<LocalInputs<GameConfig>>,
+    settings: Res<GameSettings>,
+) {
+    for dummy in &mut practice.dummies {
+        let mut input = INPUT_READY;
+
+        if !settings.still_dummies {
+            if dummy.turn_timer.tick() {
+                dummy.heading = -dummy.heading;
+                dummy.turn_timer = FrameTimer::from_seconds(DUMMY_STRAFE_SECONDS, settings.tick_rate);
+            }
+            input |= encode_analog(Vec2::X * dummy.heading);
+        }
+
+        local_inputs.0.insert(dummy.handle, input);
+    }
+}
+
+/// Brings back anyone who died on the very next frame, somewhere random
+/// that's clear of barriers and other players
+pub fn respawn_practice_players(
+    mut commands: Commands,
+    players: Query<(&Player, &Transform)>,
+    markers: Query<&SpawnMarker>,
+    barriers: Query<(&Transform, &Sprite), With<Barrier>>,
+    mut rng: ResMut<GameRng>,
+    settings: Res<GameSettings>,
+) {
+    let barrier_bounds: Vec<(Vec2, Vec2)> = barriers
+        .iter()
+        .map(|(transform, sprite)| {
+            (transform.translation.xy(), sprite.custom_size.expect("Barrier has no size"))
+        })
+        .collect();
+    let player_positions: Vec<Vec2> = players.iter().map(|(_, transform)| transform.translation.xy()).collect();
+
+    for handle in 0..NUM_PLAYERS {
+        let alive = players.iter().any(|(player, _)| player.handle == handle);
+        let respawning = markers.iter().any(|marker| marker.handle == handle);
+        if alive || respawning {
+            continue;
+        }
+
+        let position = clear_respawn_position(&mut rng, &barrier_bounds, &player_positions, settings.player_radius);
+        let direction = rng.direction();
+        spawn_marker(&mut commands, handle, position, direction, FrameTimer::from_frames(1), &settings);
+    }
+}
+
+/// Draws positions until one leaves a player clear of every barrier, given
+/// by center and size, and away from every player
+fn clear_respawn_position(rng: &mut GameRng, barriers: &[(Vec2, Vec2)], players: &[Vec2], player_radius: f32) -> Vec2 {
+    let mut position = rng.position();
+    for _ in 1..RESPAWN_ATTEMPTS {
+        let in_barrier = barriers.iter().any(|&(center, size)| {
+            ((position - center).abs() - size / 2.).cmplt(Vec2::splat(player_radius)).all()
+        });
+        let near_player = players.iter().any(|player| player.distance(position) < RESPAWN_CLEARANCE);
+        if !in_barrier && !near_player {
+            break;
+        }
+        position = rng.position();
+    }
+    position
+}
+
+/// Leaves the range for the results screen, since a practice match never ends on its own
+pub fn leave_practice_range(keyboard_input: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<AppState>>) {
+    if keyboard_input.just_pressed(LEAVE_KEY) {
+        next_state.set(AppState::GameOver);
+    }
+}
+
+fn remove_practice_range(mut commands: Commands) {
+    commands.remove_resource::<PracticeRange>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WORLD_SIZE;
+
+    #[test]
+    fn respawns_land_clear_of_barriers_and_players() {
+        let half_world_size = WORLD_SIZE as f32 / 2.;
+        // The left half of the map is walled off, and someone stands in the middle of the right
+        let barriers = [(Vec2::new(-half_world_size / 2., 0.), Vec2::new(half_world_size, WORLD_SIZE as f32))];
+        let players = [Vec2::new(half_world_size / 2., 0.)];
+        let player_radius = GameSettings::default().player_radius;
+
+        let mut rng = GameRng::default();
+        for _ in 0..100 {
+            let position = clear_respawn_position(&mut rng, &barriers, &players, player_radius);
+            assert!(position.x >= player_radius, "respawned inside the wall at {position}");
+            assert!(position.distance(players[0]) >= RESPAWN_CLEARANCE, "respawned on a player at {position}");
+        }
+    }
+}
@@ -1,61 +1,479 @@
 use bevy::prelude::*;
 use bevy_ggrs::prelude::*;
+use bevy_ggrs::RollbackFrameCount;
 use crate::{GameConfig, GameTextures};
-use crate::input_handler::is_shooting;
-use crate::player_module::{CanAttack, MovementDirection, Player, PROJECTILE_RADIUS, PLAYER_RADIUS};
+use crate::events::{PendingEvents, ProjectileFired};
+use crate::input_handler::{is_reloading, is_shooting};
+use crate::player_module::{CanAttack, MovementDirection, Player};
+use crate::settings::{FireMode, GameSettings};
+use crate::utilities::{wrap_position, FrameTimer};
+
+/// Speed projectiles travel along the ground, in world units per second
+pub const PROJECTILE_SPEED: f32 = 20.0;
+/// Slower ground speed of a lobbed grenade
+const GRENADE_SPEED: f32 = 12.0;
+/// Upward speed grenades are thrown with, in world units per second
+const GRENADE_LAUNCH_SPEED: f32 = 8.0;
+/// Seconds from throwing a grenade to it exploding, unless it hits something first
+const GRENADE_FUSE_SECONDS: f32 = 1.5;
+/// Height above which projectiles fly over players and barriers
+pub const AIRBORNE_HEIGHT: f32 = 1.0;
+/// Times a projectile can cross the edge of the map with wrapping edges before
+/// it's removed, so missed shots don't circle forever
+const PROJECTILE_WRAPS: u32 = 1;
+/// How much bigger projectiles are drawn per unit of height, to show the arc
+const HEIGHT_SCALE: f32 = 0.3;
 
 #[derive(Component, Clone, Copy)]
-pub struct Projectile;
+pub struct Projectile {
+    /// Handle of the player who fired the projectile
+    pub(crate) owner: usize,
+    /// Health removed from a player this projectile hits
+    pub(crate) damage: u32,
+    /// Sets the player it hits on fire
+    pub(crate) ignites: bool,
+    /// Player-placed barriers the projectile can still pass through
+    pub(crate) piercing: u32,
+    /// Movement along the ground, in world units per second
+    pub(crate) velocity: Vec2,
+    /// Height above the ground. Only lobbed projectiles ever leave it.
+    pub(crate) height: f32,
+    /// Speed the projectile is rising at, negative when falling
+    pub(crate) vertical_speed: f32,
+    /// Downward acceleration pulling the projectile back to the ground
+    pub(crate) gravity: f32,
+    /// Frames until the projectile explodes, for grenades
+    pub(crate) fuse: Option<FrameTimer>,
+    /// Edges of the map the projectile can still wrap across
+    pub(crate) wraps_left: u32,
+}
+
+impl Projectile {
+    /// Whether the projectile is high enough to pass over players and barriers
+    pub fn is_airborne(&self) -> bool {
+        self.height >= AIRBORNE_HEIGHT
+    }
+
+    /// Whether the projectile explodes rather than hitting what it touches
+    pub fn is_explosive(&self) -> bool {
+        self.fuse.is_some()
+    }
+
+    /// Sets an explosive projectile off on this frame
+    pub fn detonate(&mut self) {
+        self.fuse = Some(FrameTimer::default());
+    }
+
+    /// Whether the fuse has run out, so the projectile explodes this frame
+    pub fn should_explode(&self) -> bool {
+        self.fuse.is_some_and(|fuse| fuse.finished())
+    }
+}
+
+/// Rollback frame a projectile was fired on
+///
+/// Only read for drawing, to tell projectiles that could still be rolled back
+/// from confirmed ones, so it isn't registered for rollback.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpawnFrame(pub i32);
+
+/// Component for the weapon a player fires
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Weapon {
+    #[default]
+    Pistol,
+    Shotgun,
+    Rocket,
+    /// Fires incendiary rounds that burn instead of dealing damage on impact
+    Incendiary,
+    /// Fires rounds that punch through player-placed barriers
+    Railgun,
+    /// Lobs grenades over walls that explode after a fuse or on impact
+    Grenade,
+}
+
+impl Weapon {
+    /// Damage dealt by each projectile
+    pub fn damage(self) -> u32 {
+        match self {
+            Weapon::Pistol => 1,
+            Weapon::Shotgun => 1,
+            Weapon::Rocket => 3,
+            Weapon::Incendiary => 0,
+            Weapon::Railgun => 1,
+            // Grenades only hurt when they explode
+            Weapon::Grenade => 0,
+        }
+    }
+
+    /// Whether projectiles set the player they hit on fire
+    pub fn ignites(self) -> bool {
+        self == Weapon::Incendiary
+    }
+
+    /// Player-placed barriers each projectile breaks through before stopping,
+    /// however many hits they'd otherwise take. Walls that are part of the map
+    /// always stop it.
+    pub fn piercing(self) -> u32 {
+        match self {
+            Weapon::Railgun => 3,
+            Weapon::Pistol | Weapon::Shotgun | Weapon::Rocket | Weapon::Incendiary | Weapon::Grenade => 0,
+        }
+    }
+
+    /// Seconds between shots. Only holds back semi-automatic fire for players
+    /// pressing faster than this.
+    pub fn fire_interval(self) -> f32 {
+        match self {
+            Weapon::Pistol => 0.25,
+            Weapon::Shotgun => 0.8,
+            Weapon::Rocket => 1.0,
+            Weapon::Incendiary => 0.5,
+            Weapon::Railgun => 1.2,
+            Weapon::Grenade => 1.0,
+        }
+    }
+
+    /// Shots fired before the weapon has to be reloaded
+    pub fn magazine_size(self) -> u32 {
+        match self {
+            Weapon::Pistol => 12,
+            Weapon::Shotgun => 4,
+            Weapon::Rocket => 1,
+            Weapon::Incendiary => 8,
+            Weapon::Railgun => 3,
+            Weapon::Grenade => 2,
+        }
+    }
+
+    /// Spare rounds a player spawns with on top of a full magazine
+    pub fn reserve_size(self) -> u32 {
+        self.magazine_size() * 4
+    }
+
+    /// Seconds a reload takes, during which the weapon can't fire
+    pub fn reload_seconds(self) -> f32 {
+        match self {
+            Weapon::Pistol => 1.2,
+            Weapon::Shotgun => 2.0,
+            Weapon::Rocket => 2.0,
+            Weapon::Incendiary => 1.5,
+            Weapon::Railgun => 2.0,
+            Weapon::Grenade => 1.8,
+        }
+    }
+
+    /// Whether projectiles are lobbed in an arc and explode
+    pub fn lobs(self) -> bool {
+        self == Weapon::Grenade
+    }
+
+    /// Number of projectiles fired per shot
+    pub fn pellets(self) -> u32 {
+        match self {
+            Weapon::Shotgun => 5,
+            Weapon::Pistol | Weapon::Rocket | Weapon::Incendiary | Weapon::Railgun | Weapon::Grenade => 1,
+        }
+    }
+
+    /// Angle in radians between the outermost pellets
+    pub fn spread(self) -> f32 {
+        match self {
+            Weapon::Shotgun => 0.4,
+            Weapon::Pistol | Weapon::Rocket | Weapon::Incendiary | Weapon::Railgun | Weapon::Grenade => 0.0,
+        }
+    }
+}
+
+/// Component for the rounds a player has left in their weapon
+///
+/// Firing empties the magazine one shot at a time. Reloading refills it from
+/// the reserve once the weapon's reload time has passed, and nothing can be
+/// fired in the meantime.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ammo {
+    pub in_mag: u32,
+    pub reserve: u32,
+    /// Frames until the reload finishes, while reloading
+    pub reloading: Option<FrameTimer>,
+}
+
+impl Ammo {
+    /// A full magazine and reserve for `weapon`
+    pub fn full(weapon: Weapon) -> Self {
+        Ammo {
+            in_mag: weapon.magazine_size(),
+            reserve: weapon.reserve_size(),
+            reloading: None,
+        }
+    }
+
+    pub fn can_fire(&self) -> bool {
+        self.in_mag > 0 && self.reloading.is_none()
+    }
+
+    /// Starts a reload, unless one is underway, the magazine is full or there's nothing left to load
+    pub fn start_reload(&mut self, weapon: Weapon, tick_rate: usize) {
+        if self.reloading.is_some() || self.in_mag >= weapon.magazine_size() || self.reserve == 0 {
+            return;
+        }
+        self.reloading = Some(FrameTimer::from_seconds(weapon.reload_seconds(), tick_rate));
+    }
+
+    /// Advances a reload, moving rounds from the reserve into the magazine once it finishes
+    pub fn tick_reload(&mut self, weapon: Weapon) {
+        let Some(timer) = self.reloading.as_mut() else {
+            return;
+        };
+        timer.tick();
+        if !timer.finished() {
+            return;
+        }
+
+        let loaded = (weapon.magazine_size() - self.in_mag).min(self.reserve);
+        self.in_mag += loaded;
+        self.reserve -= loaded;
+        self.reloading = None;
+    }
+}
 
 pub fn fire_projectile(
     mut commands: Commands,
     inputs: Res<PlayerInputs<GameConfig>>,
     images: Res<GameTextures>,
-    mut players: Query<(&Transform, &Player, &mut CanAttack, &MovementDirection)>
+    mut players: Query<(&Transform, &Player, &mut CanAttack, &mut Ammo, &MovementDirection, &Weapon)>,
+    mut pending_events: ResMut<PendingEvents>,
+    frame: Res<RollbackFrameCount>,
+    settings: Res<GameSettings>,
 ) {
-    for (transform, player, mut attack_ready, movement_direction) in &mut players {
+    for (transform, player, mut attack, mut ammo, movement_direction, weapon) in &mut players {
         let (input, _) = inputs[player.handle];
-        if is_shooting(input) && attack_ready.0 {
+
+        // Presses are detected from the previous frame's input, which is
+        // rolled back with the component so every peer sees the same edges
+        let held = is_shooting(input);
+        let pressed = held && !attack.trigger_held;
+        attack.trigger_held = held;
+
+        let wants_to_fire = match settings.fire_mode {
+            FireMode::Automatic => held,
+            FireMode::SemiAuto => pressed,
+        };
+        if wants_to_fire && attack.cooldown.finished() && ammo.can_fire() {
             let player_pos = transform.translation.xy();
-            let pos = player_pos + movement_direction.0 * PLAYER_RADIUS + PROJECTILE_RADIUS;
-            commands
-                .spawn((
-                    Projectile,
-                    *movement_direction,
-                SpriteBundle {
-                    transform: Transform::from_translation(pos.extend(200.0)),
-                    texture: images.projectile_image.clone(),
-                    sprite: Sprite {
-                        custom_size: Some(Vec2::new(1.0, 1.0)),
+            let pos = player_pos + movement_direction.0 * settings.player_radius + settings.projectile_radius;
+
+            // Pellets fan out evenly across the weapon's spread
+            let pellets = weapon.pellets();
+            for pellet in 0..pellets {
+                let offset = if pellets > 1 {
+                    weapon.spread() * (pellet as f32 / (pellets - 1) as f32 - 0.5)
+                } else {
+                    0.0
+                };
+                let pellet_direction = Vec2::from_angle(offset).rotate(movement_direction.0);
+
+                let (speed, vertical_speed, gravity, fuse) = if weapon.lobs() {
+                    (
+                        GRENADE_SPEED,
+                        GRENADE_LAUNCH_SPEED,
+                        settings.grenade_gravity,
+                        Some(FrameTimer::from_seconds(GRENADE_FUSE_SECONDS, settings.tick_rate)),
+                    )
+                } else {
+                    (PROJECTILE_SPEED, 0.0, 0.0, None)
+                };
+
+                commands
+                    .spawn((
+                        Projectile {
+                            owner: player.handle,
+                            damage: weapon.damage() * settings.damage_multiplier,
+                            ignites: weapon.ignites(),
+                            piercing: weapon.piercing(),
+                            velocity: pellet_direction * speed,
+                            height: 0.0,
+                            vertical_speed,
+                            gravity,
+                            fuse,
+                            wraps_left: PROJECTILE_WRAPS,
+                        },
+                        MovementDirection(pellet_direction),
+                        SpawnFrame(frame.0),
+                    SpriteBundle {
+                        transform: Transform::from_translation(pos.extend(200.0)),
+                        texture: images.projectile_image.clone(),
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::new(1.0, 1.0)),
+                            ..Default::default()
+                        },
                         ..Default::default()
                     },
-                    ..Default::default()
-                },
-                )).add_rollback();
-            attack_ready.0 = false;
+                    )).add_rollback();
+            }
+            attack.cooldown = FrameTimer::from_seconds(weapon.fire_interval(), settings.tick_rate);
+            ammo.in_mag -= 1;
+            pending_events.push(&frame, ProjectileFired { owner: player.handle });
         }
     }
 }
 
+/// Counts down each weapon's cooldown between shots and carries out reloads
+///
+/// A reload starts when the player asks for one or their magazine runs dry.
 pub fn reload_projectile(
+    mut players: Query<(&Player, &mut CanAttack, &mut Ammo, &Weapon)>,
     inputs: Res<PlayerInputs<GameConfig>>,
-    mut players: Query<(&mut CanAttack, &Player)>
+    settings: Res<GameSettings>,
 ) {
-    for (mut can_attack, player) in players.iter_mut() {
+    for (player, mut attack, mut ammo, weapon) in &mut players {
+        attack.cooldown.tick();
+
         let (input, _) = inputs[player.handle];
-        if !is_shooting(input) {
-            can_attack.0 = true;
+        if is_reloading(input) || ammo.in_mag == 0 {
+            ammo.start_reload(*weapon, settings.tick_rate);
         }
+        ammo.tick_reload(*weapon);
     }
 }
 
+/// Moves projectiles along the ground and through the air, and burns down grenade fuses
+///
+/// With wrapping edges, projectiles leaving the map come back on the opposite
+/// side until they run out of wraps.
 pub fn move_projectile(
-    mut projectiles: Query<(&mut Transform, &MovementDirection), With<Projectile>>,
-    time: Res<Time>
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &mut Transform, &mut Projectile)>,
+    settings: Res<GameSettings>,
 ) {
-    for (mut transform, move_dir) in &mut projectiles {
-        let speed = 20.0;
-        let delta = move_dir.0 * speed * time.delta_seconds();
-        transform.translation += delta.extend(0.0);
+    let delta_seconds = settings.frame_delta();
+
+    for (entity, mut transform, mut projectile) in &mut projectiles {
+        transform.translation += (projectile.velocity * delta_seconds).extend(0.0);
+
+        if settings.wrap_edges {
+            let position = transform.translation.xy();
+            let wrapped = wrap_position(position);
+            if wrapped != position {
+                if projectile.wraps_left == 0 {
+                    commands.entity(entity).despawn_recursive();
+                    continue;
+                }
+                projectile.wraps_left -= 1;
+                transform.translation = wrapped.extend(transform.translation.z);
+            }
+        }
+
+        if projectile.gravity > 0.0 {
+            projectile.vertical_speed -= projectile.gravity * delta_seconds;
+            projectile.height += projectile.vertical_speed * delta_seconds;
+
+            // Lobbed projectiles stop where they land and wait for their fuse
+            if projectile.height <= 0.0 {
+                projectile.height = 0.0;
+                projectile.vertical_speed = 0.0;
+                projectile.velocity = Vec2::ZERO;
+            }
+        }
+        transform.scale = Vec3::splat(1.0 + projectile.height * HEIGHT_SCALE);
+
+        if let Some(fuse) = projectile.fuse.as_mut() {
+            fuse.tick();
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_RATE: usize = 60;
+
+    const WEAPONS: [Weapon; 6] = [
+        Weapon::Pistol,
+        Weapon::Shotgun,
+        Weapon::Rocket,
+        Weapon::Incendiary,
+        Weapon::Railgun,
+        Weapon::Grenade,
+    ];
+
+    #[test]
+    fn only_fire_and_grenades_skip_impact_damage() {
+        for weapon in WEAPONS {
+            let deals_damage_elsewhere = weapon.ignites() || weapon.lobs();
+            assert_eq!(weapon.damage() == 0, deals_damage_elsewhere, "{weapon:?}");
+        }
+        assert!(Weapon::Rocket.damage() > Weapon::Pistol.damage());
+    }
+
+    #[test]
+    fn only_spreading_weapons_fire_several_pellets() {
+        for weapon in WEAPONS {
+            assert!(weapon.pellets() >= 1, "{weapon:?}");
+            assert_eq!(weapon.pellets() > 1, weapon.spread() > 0.0, "{weapon:?}");
+            assert!(weapon.fire_interval() > 0.0 && weapon.reload_seconds() > 0.0, "{weapon:?}");
+        }
+    }
+
+    /// Fires until the magazine is empty
+    fn empty_magazine(ammo: &mut Ammo) {
+        while ammo.can_fire() {
+            ammo.in_mag -= 1;
+        }
+    }
+
+    #[test]
+    fn reload_refills_the_magazine_from_the_reserve_once_it_finishes() {
+        let weapon = Weapon::Pistol;
+        let mut ammo = Ammo::full(weapon);
+        empty_magazine(&mut ammo);
+        assert_eq!(ammo.in_mag, 0);
+
+        ammo.start_reload(weapon, TICK_RATE);
+        let reload_frames = ammo.reloading.unwrap().frames_remaining;
+        for _ in 1..reload_frames {
+            ammo.tick_reload(weapon);
+            assert!(!ammo.can_fire());
+        }
+        ammo.tick_reload(weapon);
+
+        assert!(ammo.can_fire());
+        assert_eq!(ammo.in_mag, weapon.magazine_size());
+        assert_eq!(ammo.reserve, weapon.reserve_size() - weapon.magazine_size());
+    }
+
+    #[test]
+    fn reload_only_loads_what_is_left_in_the_reserve() {
+        let weapon = Weapon::Shotgun;
+        let mut ammo = Ammo {
+            in_mag: 1,
+            reserve: 2,
+            reloading: None,
+        };
+        ammo.start_reload(weapon, TICK_RATE);
+        while ammo.reloading.is_some() {
+            ammo.tick_reload(weapon);
+        }
+        assert_eq!((ammo.in_mag, ammo.reserve), (3, 0));
+
+        // Nothing left to load, so no reload starts
+        ammo.start_reload(weapon, TICK_RATE);
+        assert!(ammo.reloading.is_none());
+    }
+
+    #[test]
+    fn full_magazines_and_running_reloads_are_left_alone() {
+        let weapon = Weapon::Rocket;
+        let mut ammo = Ammo::full(weapon);
+        ammo.start_reload(weapon, TICK_RATE);
+        assert!(ammo.reloading.is_none());
+
+        empty_magazine(&mut ammo);
+        ammo.start_reload(weapon, TICK_RATE);
+        ammo.tick_reload(weapon);
+        let remaining = ammo.reloading.unwrap().frames_remaining;
+        ammo.start_reload(weapon, TICK_RATE);
+        assert_eq!(ammo.reloading.unwrap().frames_remaining, remaining);
+    }
+}
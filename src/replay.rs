@@ -0,0 +1,546 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ggrs::{
+    ConfirmedFrameCount, GgrsSchedule, LocalInputs, PlayerInputs, ReadInputs, RollbackFrameCount, Session,
+};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use crate::{AppState, GameConfig};
+use crate::input_handler::collect_player_inputs;
+use crate::network_manager::{LocalRole, Opponents, RandomSeed, NUM_PLAYERS};
+use crate::settings::GameSettings;
+
+// A replay is the match seed, the settings and every player's input on every
+// frame. The simulation is deterministic, so feeding the same inputs into a
+// session built from the same settings and seed plays the match out again.
+//
+// Files start with a magic number and a format version, read on their own so
+// files from another version are turned away before the rest is decoded.
+// Inputs rarely change between frames, so only changes are stored, each with
+// the number of frames since the one before it, all encoded with variable
+// length integers.
+
+/// First bytes of every replay file
+const REPLAY_MAGIC: [u8; 4] = *b"CRPL";
+/// Bumped whenever the layout of the body or the meaning of the input bits changes
+const REPLAY_VERSION: u16 = 2;
+/// Largest file accepted, so a corrupt length can't allocate without bound
+const MAX_REPLAY_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Longest replay accepted, two hours at the highest tick rate
+const MAX_REPLAY_FRAMES: u32 = 120 * 60 * 60 * 2;
+
+/// Flags that aren't part of the match and are left out of a replay's settings
+const NON_GAMEPLAY_FLAGS: [&str; 4] = ["--record-replay", "--replay", "--check-determinism", "--config"];
+
+/// A recorded match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: u64,
+    /// Settings file the match was played with, overridden by `args`
+    pub config: Option<String>,
+    /// Command line the match's settings are parsed from
+    pub args: Vec<String>,
+    /// Every player's input by frame, then by handle
+    pub inputs: Vec<Vec<u64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayPreamble {
+    magic: [u8; 4],
+    version: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayBody {
+    seed: u64,
+    num_players: u8,
+    config: Option<String>,
+    args: Vec<String>,
+    frame_count: u32,
+    changes: Vec<InputChange>,
+}
+
+/// A player's input changing
+#[derive(Serialize, Deserialize)]
+struct InputChange {
+    /// Frames since the previous change, or since the start for the first one
+    frames_since_last: u32,
+    player: u8,
+    input: u64,
+}
+
+/// Why a replay couldn't be saved or loaded
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Codec(bincode::Error),
+    NotAReplay,
+    UnsupportedVersion(u16),
+    Invalid(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "could not access replay: {error}"),
+            ReplayError::Codec(error) => write!(f, "could not encode or decode replay: {error}"),
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "replay is format version {version}, but this build only plays version {REPLAY_VERSION}"
+            ),
+            ReplayError::Invalid(reason) => write!(f, "invalid replay: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(error: std::io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(error: bincode::Error) -> Self {
+        ReplayError::Codec(error)
+    }
+}
+
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(MAX_REPLAY_BYTES)
+}
+
+/// Writes a replay to `path`
+pub fn save_replay(path: &Path, replay: &Replay) -> Result<(), ReplayError> {
+    let mut changes = Vec::new();
+    let mut previous = vec![0u64; NUM_PLAYERS];
+    let mut last_change_frame = 0;
+
+    for (frame, inputs) in replay.inputs.iter().enumerate() {
+        for (player, (&input, previous)) in inputs.iter().zip(&mut previous).enumerate() {
+            if input == *previous {
+                continue;
+            }
+            changes.push(InputChange {
+                frames_since_last: (frame - last_change_frame) as u32,
+                player: player as u8,
+                input,
+            });
+            *previous = input;
+            last_change_frame = frame;
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    let codec = codec();
+    codec.serialize_into(
+        &mut writer,
+        &ReplayPreamble {
+            magic: REPLAY_MAGIC,
+            version: REPLAY_VERSION,
+        },
+    )?;
+    codec.serialize_into(
+        &mut writer,
+        &ReplayBody {
+            seed: replay.seed,
+            num_players: NUM_PLAYERS as u8,
+            config: replay.config.clone(),
+            args: replay.args.clone(),
+            frame_count: replay.inputs.len() as u32,
+            changes,
+        },
+    )?;
+    Ok(())
+}
+
+/// Reads and checks a replay written by `save_replay`
+pub fn load_replay(path: &Path) -> Result<Replay, ReplayError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let codec = codec();
+
+    let preamble: ReplayPreamble = codec
+        .deserialize_from(&mut reader)
+        .map_err(|_| ReplayError::NotAReplay)?;
+    if preamble.magic != REPLAY_MAGIC {
+        return Err(ReplayError::NotAReplay);
+    }
+    if preamble.version != REPLAY_VERSION {
+        return Err(ReplayError::UnsupportedVersion(preamble.version));
+    }
+
+    let body: ReplayBody = codec.deserialize_from(&mut reader)?;
+    if body.num_players as usize != NUM_PLAYERS {
+        return Err(ReplayError::Invalid(format!(
+            "recorded with {} players, but this build plays with {NUM_PLAYERS}",
+            body.num_players
+        )));
+    }
+    GameSettings::load(body.config.as_deref(), &body.args)
+        .map_err(|error| ReplayError::Invalid(format!("bad settings: {error}")))?;
+
+    if body.frame_count > MAX_REPLAY_FRAMES {
+        return Err(ReplayError::Invalid(format!(
+            "{} frames is longer than any match",
+            body.frame_count
+        )));
+    }
+
+    let mut changes = Vec::with_capacity(body.changes.len());
+    let mut frame = 0u32;
+    for change in &body.changes {
+        frame = frame.saturating_add(change.frames_since_last);
+        let player = change.player as usize;
+        if frame >= body.frame_count || player >= NUM_PLAYERS {
+            return Err(ReplayError::Invalid(format!(
+                "input change for player {player} on frame {frame} is out of range"
+            )));
+        }
+        changes.push((frame, player, change.input));
+    }
+
+    // Every frame holds each player's input from their last change
+    let mut current = vec![0u64; NUM_PLAYERS];
+    let mut changes = changes.into_iter().peekable();
+    let inputs = (0..body.frame_count)
+        .map(|frame| {
+            while let Some((_, player, input)) = changes.next_if(|&(change_frame, _, _)| change_frame == frame) {
+                current[player] = input;
+            }
+            current.clone()
+        })
+        .collect();
+
+    Ok(Replay {
+        seed: body.seed,
+        config: body.config,
+        args: body.args,
+        inputs,
+    })
+}
+
+/// The command line without the flags that only affect this run
+pub fn gameplay_args(args: &[String]) -> Vec<String> {
+    let mut gameplay_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+        if NON_GAMEPLAY_FLAGS.contains(&flag) {
+            // Skip the value too, unless it was given inline
+            if !arg.contains('=') {
+                args.next();
+            }
+            continue;
+        }
+        gameplay_args.push(arg.clone());
+    }
+    gameplay_args
+}
+
+/// Settings file and command line the game was started with, minus the
+/// flags that only affect this run
+#[derive(Resource, Clone, Default)]
+pub struct LaunchArgs {
+    pub config: Option<String>,
+    pub args: Vec<String>,
+}
+
+/// Inputs of the match being played, kept to save as a replay when it ends
+///
+/// Frames are overwritten when a rollback simulates them again, so only the
+/// corrected inputs are kept.
+#[derive(Resource, Default)]
+struct ReplayRecorder {
+    first_frame: Option<i32>,
+    inputs: Vec<Vec<u64>>,
+}
+
+/// A replay being watched, fed into a local session frame by frame
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    next_frame: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            next_frame: 0,
+        }
+    }
+}
+
+/// Registers replay recording and playback to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<LaunchArgs>()
+        .add_systems(
+            OnEnter(AppState::MainMenu),
+            watch_replay.run_if(resource_exists::<ReplayPlayback>),
+        )
+        .add_systems(
+            OnEnter(AppState::Connecting),
+            (
+                start_recording.run_if(|settings: Res<GameSettings>| settings.record_replay.is_some()),
+                start_replay_session.run_if(resource_equals(Opponents::Replay)),
+            ),
+        )
+        .add_systems(
+            ReadInputs,
+            play_replay_inputs
+                .after(collect_player_inputs)
+                .run_if(resource_exists::<ReplayPlayback>),
+        )
+        .add_systems(
+            GgrsSchedule,
+            record_inputs.run_if(resource_exists::<ReplayRecorder>),
+        )
+        .add_systems(
+            OnEnter(AppState::GameOver),
+            save_recorded_replay.run_if(resource_exists::<ReplayRecorder>),
+        );
+}
+
+/// Goes straight into the replay instead of waiting on the main menu
+fn watch_replay(mut opponents: ResMut<Opponents>, mut next_state: ResMut<NextState<AppState>>) {
+    *opponents = Opponents::Replay;
+    next_state.set(AppState::Connecting);
+}
+
+fn start_recording(mut commands: Commands, playback: Option<Res<ReplayPlayback>>, opponents: Res<Opponents>) {
+    // A replay of a replay would only be a copy
+    if playback.is_some() {
+        return;
+    }
+    // Replays are always played back as a match, so the practice range's
+    // respawns and endless rounds can't be recreated from one
+    if *opponents == Opponents::Practice {
+        warn!("Practice sessions can't be recorded, not saving a replay");
+        return;
+    }
+    commands.insert_resource(ReplayRecorder::default());
+}
+
+/// Starts a local session where every player's inputs come from the replay
+///
+/// Like a bot match, it's a sync test session with no check distance, so
+/// every frame is simulated exactly once.
+fn start_replay_session(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    mut next_state: ResMut<NextState<AppState>>,
+    settings: Res<GameSettings>,
+) {
+    playback.next_frame = 0;
+    commands.insert_resource(RandomSeed(playback.replay.seed));
+
+    let mut session_builder = ggrs::SessionBuilder::<GameConfig>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_fps(settings.tick_rate)
+        .expect("Tick rate was validated when parsing settings")
+        .with_check_distance(0);
+    for handle in 0..NUM_PLAYERS {
+        session_builder = session_builder
+            .add_player(ggrs::PlayerType::Local, handle)
+            .expect("Failed to add player to session");
+    }
+    let ggrs_session = session_builder
+        .start_synctest_session()
+        .expect("Failed to start replay session");
+
+    // Nobody watching a replay controls a player
+    commands.insert_resource(LocalRole::Spectator);
+    commands.insert_resource(Session::SyncTest(ggrs_session));
+    next_state.set(AppState::InGame);
+}
+
+/// Feeds the recorded inputs for the next frame, and idle inputs once the replay runs out
+fn play_replay_inputs(mut playback: ResMut<ReplayPlayback>, mut local_inputs: ResMut<LocalInputs<GameConfig>>) {
+    let frame = playback.next_frame;
+    playback.next_frame += 1;
+    if frame == playback.replay.inputs.len() {
+        info!("Replay finished after {frame} frames");
+    }
+
+    let inputs = playback.replay.inputs.get(frame);
+    local_inputs.0 = (0..NUM_PLAYERS)
+        .map(|handle| (handle, inputs.map_or(0, |inputs| inputs[handle])))
+        .collect::<HashMap<_, _>>();
+}
+
+fn record_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    inputs: Res<PlayerInputs<GameConfig>>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let first_frame = *recorder.first_frame.get_or_insert(frame.0);
+    let Ok(index) = usize::try_from(frame.0 - first_frame) else {
+        return;
+    };
+
+    let frame_inputs: Vec<u64> = (0..NUM_PLAYERS).map(|handle| inputs[handle].0).collect();
+    if index >= recorder.inputs.len() {
+        recorder.inputs.resize(index + 1, vec![0; NUM_PLAYERS]);
+    }
+    recorder.inputs[index] = frame_inputs;
+}
+
+/// Writes the finished match to the replay file, leaving out frames that were
+/// only predicted
+fn save_recorded_replay(
+    mut commands: Commands,
+    mut recorder: ResMut<ReplayRecorder>,
+    confirmed_frame: Option<Res<ConfirmedFrameCount>>,
+    seed: Res<RandomSeed>,
+    args: Res<LaunchArgs>,
+    settings: Res<GameSettings>,
+) {
+    commands.remove_resource::<ReplayRecorder>();
+    let Some(path) = settings.record_replay.as_deref() else {
+        return;
+    };
+
+    if let (Some(first_frame), Some(confirmed_frame)) = (recorder.first_frame, confirmed_frame) {
+        let confirmed_len = usize::try_from(confirmed_frame.0 - first_frame + 1).unwrap_or(0);
+        recorder.inputs.truncate(confirmed_len);
+    }
+
+    let replay = Replay {
+        seed: **seed,
+        config: args.config.clone(),
+        args: args.args.clone(),
+        inputs: std::mem::take(&mut recorder.inputs),
+    };
+    match save_replay(path, &replay) {
+        Ok(()) => info!("Saved replay of {} frames to {}", replay.inputs.len(), path.display()),
+        Err(error) => error!("Failed to save replay to {}: {error}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file in the temp directory only this test writes to
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("replay-test-{}-{name}.replay", std::process::id()))
+    }
+
+    fn write_raw(path: &Path, preamble: &ReplayPreamble, body: &ReplayBody) {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        codec().serialize_into(&mut writer, preamble).unwrap();
+        codec().serialize_into(&mut writer, body).unwrap();
+    }
+
+    fn current_preamble() -> ReplayPreamble {
+        ReplayPreamble {
+            magic: REPLAY_MAGIC,
+            version: REPLAY_VERSION,
+        }
+    }
+
+    fn body_with_changes(frame_count: u32, changes: Vec<InputChange>) -> ReplayBody {
+        ReplayBody {
+            seed: 7,
+            num_players: NUM_PLAYERS as u8,
+            config: None,
+            args: Vec::new(),
+            frame_count,
+            changes,
+        }
+    }
+
+    fn sample_replay() -> Replay {
+        let inputs = (0..50u64)
+            .map(|frame| (0..NUM_PLAYERS as u64).map(|handle| (frame / (handle + 3)) % 4).collect())
+            .collect();
+        Replay {
+            seed: 0xDEAD_BEEF,
+            config: Some("wall-slam = true\n".to_string()),
+            args: vec!["--weapon".to_string(), "shotgun".to_string()],
+            inputs,
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_the_header_and_inputs() {
+        let path = temp_path("round-trip");
+        let replay = sample_replay();
+        save_replay(&path, &replay).unwrap();
+        let loaded = load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), replay);
+    }
+
+    #[test]
+    fn rejects_a_bad_magic_number() {
+        let path = temp_path("bad-magic");
+        let preamble = ReplayPreamble {
+            magic: *b"NOPE",
+            version: REPLAY_VERSION,
+        };
+        write_raw(&path, &preamble, &body_with_changes(0, Vec::new()));
+        let loaded = load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(ReplayError::NotAReplay)));
+    }
+
+    #[test]
+    fn rejects_another_version() {
+        let path = temp_path("version");
+        let preamble = ReplayPreamble {
+            magic: REPLAY_MAGIC,
+            version: REPLAY_VERSION + 1,
+        };
+        write_raw(&path, &preamble, &body_with_changes(0, Vec::new()));
+        let loaded = load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(ReplayError::UnsupportedVersion(version)) if version == REPLAY_VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let path = temp_path("truncated");
+        save_replay(&path, &sample_replay()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let loaded = load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(ReplayError::Codec(_))));
+    }
+
+    #[test]
+    fn rejects_out_of_range_changes() {
+        let path = temp_path("out-of-range");
+        let past_the_end = InputChange {
+            frames_since_last: 10,
+            player: 0,
+            input: 1,
+        };
+        write_raw(&path, &current_preamble(), &body_with_changes(10, vec![past_the_end]));
+        let past_the_end = load_replay(&path);
+
+        let unknown_player = InputChange {
+            frames_since_last: 0,
+            player: NUM_PLAYERS as u8,
+            input: 1,
+        };
+        write_raw(&path, &current_preamble(), &body_with_changes(10, vec![unknown_player]));
+        let unknown_player = load_replay(&path);
+
+        write_raw(&path, &current_preamble(), &body_with_changes(MAX_REPLAY_FRAMES + 1, Vec::new()));
+        let too_long = load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(past_the_end, Err(ReplayError::Invalid(_))));
+        assert!(matches!(unknown_player, Err(ReplayError::Invalid(_))));
+        assert!(matches!(too_long, Err(ReplayError::Invalid(_))));
+    }
+}
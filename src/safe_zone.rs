@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy_ggrs::GgrsSchedule;
+use crate::{GamePhase, WORLD_SIZE};
+use crate::settings::GameSettings;
+use crate::utilities::FrameTimer;
+
+/// Radius the zone stops shrinking at, in world units
+const SAFE_ZONE_MIN_RADIUS: f32 = 4.0;
+/// Seconds between each hit of damage dealt to players outside the zone
+const SAFE_ZONE_DAMAGE_INTERVAL_SECONDS: f32 = 1.0;
+/// Damage dealt to players outside the zone every interval
+pub const SAFE_ZONE_DAMAGE: u32 = 1;
+
+/// The circle players have to stay inside, which closes in over the round
+///
+/// It starts around the whole map and shrinks by the same amount every
+/// rollback frame, so it's in the same place on every peer.
+#[derive(Resource, Clone, Copy)]
+pub struct SafeZone {
+    pub center: Vec2,
+    pub radius: f32,
+    /// Frames until players outside the zone are hurt again
+    damage_timer: FrameTimer,
+}
+
+impl SafeZone {
+    /// A zone covering the whole map, with a full interval before the first hit
+    fn new(settings: &GameSettings) -> Self {
+        SafeZone {
+            center: Vec2::ZERO,
+            radius: start_radius(),
+            damage_timer: FrameTimer::from_seconds(SAFE_ZONE_DAMAGE_INTERVAL_SECONDS, settings.tick_rate),
+        }
+    }
+
+    pub fn contains(&self, position: Vec2) -> bool {
+        position.distance(self.center) <= self.radius
+    }
+
+    /// Advances the damage interval, returning true on the frames players
+    /// outside the zone get hurt
+    pub fn tick_damage(&mut self, settings: &GameSettings) -> bool {
+        if !self.damage_timer.tick() {
+            return false;
+        }
+        self.damage_timer = FrameTimer::from_seconds(SAFE_ZONE_DAMAGE_INTERVAL_SECONDS, settings.tick_rate);
+        true
+    }
+}
+
+impl Default for SafeZone {
+    fn default() -> Self {
+        SafeZone::new(&GameSettings::default())
+    }
+}
+
+/// Radius reaching the corners of the map
+fn start_radius() -> f32 {
+    WORLD_SIZE as f32 * std::f32::consts::FRAC_1_SQRT_2
+}
+
+/// Whether the match was started with a shrinking safe zone
+pub fn safe_zone_enabled(settings: Res<GameSettings>) -> bool {
+    settings.safe_zone_seconds.is_some()
+}
+
+/// Registers the safe zone to the app
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<SafeZone>()
+        .add_systems(OnEnter(GamePhase::ActiveRound), reset_safe_zone)
+        .add_systems(OnEnter(GamePhase::SuddenDeath), reset_safe_zone)
+        .add_systems(
+            GgrsSchedule,
+            shrink_safe_zone
+                .after(bevy_roll_safe::apply_state_transition::<GamePhase>)
+                .run_if(in_state(GamePhase::ActiveRound).or_else(in_state(GamePhase::SuddenDeath)))
+                .run_if(safe_zone_enabled),
+        );
+}
+
+fn reset_safe_zone(mut zone: ResMut<SafeZone>, settings: Res<GameSettings>) {
+    *zone = SafeZone::new(&settings);
+}
+
+/// Closes the zone in at a steady rate until it reaches its smallest size
+pub fn shrink_safe_zone(mut zone: ResMut<SafeZone>, settings: Res<GameSettings>) {
+    let Some(seconds) = settings.safe_zone_seconds else {
+        return;
+    };
+
+    let shrink_per_second = (start_radius() - SAFE_ZONE_MIN_RADIUS) / seconds;
+    zone.radius = (zone.radius - shrink_per_second * settings.frame_delta()).max(SAFE_ZONE_MIN_RADIUS);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+    use crate::player_module::{damage_outside_safe_zone, round_outcome_world, Health, Player};
+
+    #[test]
+    fn shrinks_to_its_smallest_size_over_the_configured_time() {
+        let settings = GameSettings {
+            safe_zone_seconds: Some(2.0),
+            ..default()
+        };
+        let frames = 2 * settings.tick_rate;
+        let mut world = World::new();
+        world.insert_resource(SafeZone::new(&settings));
+        world.insert_resource(settings);
+
+        let mut last_radius = start_radius();
+        for _ in 0..frames - 1 {
+            world.run_system_once(shrink_safe_zone);
+            let radius = world.resource::<SafeZone>().radius;
+            assert!(radius < last_radius);
+            last_radius = radius;
+        }
+        assert!(last_radius > SAFE_ZONE_MIN_RADIUS);
+
+        for _ in 0..10 {
+            world.run_system_once(shrink_safe_zone);
+        }
+        assert_eq!(world.resource::<SafeZone>().radius, SAFE_ZONE_MIN_RADIUS);
+    }
+
+    #[test]
+    fn hurts_only_players_outside_once_per_interval() {
+        let settings = GameSettings::default();
+        let interval = FrameTimer::from_seconds(SAFE_ZONE_DAMAGE_INTERVAL_SECONDS, settings.tick_rate)
+            .frames_remaining;
+        let mut world = round_outcome_world(settings.clone());
+        let mut zone = SafeZone::new(&settings);
+        zone.radius = SAFE_ZONE_MIN_RADIUS;
+        world.insert_resource(zone);
+
+        let spawn = |world: &mut World, handle: usize, position: Vec2| {
+            let player = Player {
+                speed: 1.0,
+                handle,
+                color: Color::WHITE,
+            };
+            world.spawn((player, Health(10), Transform::from_translation(position.extend(0.)))).id()
+        };
+        let inside = spawn(&mut world, 0, Vec2::ZERO);
+        let outside = spawn(&mut world, 1, Vec2::splat(SAFE_ZONE_MIN_RADIUS));
+
+        for _ in 0..2 * interval {
+            world.run_system_once(damage_outside_safe_zone);
+        }
+        assert_eq!(world.get::<Health>(inside).unwrap().0, 10);
+        assert_eq!(world.get::<Health>(outside).unwrap().0, 10 - 2 * SAFE_ZONE_DAMAGE);
+    }
+}
@@ -0,0 +1,785 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use bevy::prelude::*;
+use serde::Deserialize;
+use crate::maps::{MapChoice, PRESET_MAPS};
+use crate::player_module::{PLAYER_RADIUS, PROJECTILE_RADIUS};
+use crate::projectile::Weapon;
+
+/// Room everyone joins when no room code is given
+const DEFAULT_ROOM: &str = "cis1905";
+/// Longest room code accepted, keeping the matchbox URL short
+const MAX_ROOM_CODE_LENGTH: usize = 32;
+
+/// Settings file read at startup, unless `--config` names another one
+const DEFAULT_CONFIG_PATH: &str = "settings.toml";
+
+/// Rollback frames simulated per second unless `--tick-rate` is given (the GGRS default)
+const DEFAULT_TICK_RATE: usize = 60;
+
+/// Default player colors by handle, chosen to stand out from each other and the grey map
+const DEFAULT_PLAYER_COLORS: [Color; 8] = [
+    Color::srgb(0.90, 0.16, 0.16),
+    Color::srgb(0.16, 0.40, 0.95),
+    Color::srgb(0.15, 0.80, 0.25),
+    Color::srgb(0.98, 0.80, 0.10),
+    Color::srgb(0.70, 0.20, 0.90),
+    Color::srgb(0.10, 0.85, 0.85),
+    Color::srgb(1.00, 0.50, 0.05),
+    Color::srgb(1.00, 0.40, 0.75),
+];
+
+/// How a match is won
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameMode {
+    /// The first player to reach the winning score wins
+    #[default]
+    ScoreLimit,
+    /// After a fixed number of rounds the highest score wins, with a
+    /// sudden death round breaking any tie
+    BestOf,
+    /// Every death costs a life, players out of lives sit out the remaining
+    /// rounds and the last player with lives left wins
+    Elimination,
+    /// Destroying an opponent's placed barrier scores a point and the first
+    /// player to the barrier target wins. Winning a round scores nothing.
+    Demolition,
+}
+
+/// How holding the fire button behaves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FireMode {
+    /// Fires again every time the weapon's cooldown runs out while the button is held
+    Automatic,
+    /// Fires once per press, so the button has to be released between shots
+    #[default]
+    SemiAuto,
+}
+
+/// When to draw the local player's line of fire up to the first barrier
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AimPreview {
+    #[default]
+    Off,
+    Always,
+    WhileShooting,
+}
+
+/// How well bots play
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AiDifficulty {
+    /// Slow to react, aims loosely and rarely fires
+    Easy,
+    #[default]
+    Normal,
+    /// Reacts quickly and leads moving targets
+    Hard,
+}
+
+/// Match options chosen at startup
+#[derive(Resource, Clone, Debug)]
+pub struct GameSettings {
+    /// Matchbox room to join. Friends sharing a code matchmake privately.
+    pub room: String,
+    /// Hide opponents and projectiles the local player can't see
+    pub fog_of_war: bool,
+    /// Deal bonus damage when a hit knocks a player into a barrier
+    pub wall_slam: bool,
+    /// Leave out the walls around the map, so players and projectiles
+    /// crossing one edge come back in on the opposite side
+    pub wrap_edges: bool,
+    /// Keep the practice range's target dummies standing still instead of strafing
+    pub still_dummies: bool,
+    /// Movement speed every player spawns with, in world units per second
+    pub base_player_speed: f32,
+    pub game_mode: GameMode,
+    /// Score a player needs to win a score limit match
+    pub winning_score: u32,
+    /// Rounds in a best-of match before sudden death
+    pub max_rounds: u32,
+    /// Lives each player starts an elimination match with
+    pub lives: u32,
+    /// Barriers a player has to destroy to win a demolition match
+    pub barrier_target: u32,
+    /// Color of each player by handle
+    pub player_colors: Vec<Color>,
+    pub aim_preview: AimPreview,
+    /// Every peer must choose the same fire mode
+    pub fire_mode: FireMode,
+    /// Weapon every player spawns with. Every peer must choose the same one.
+    pub starting_weapon: Weapon,
+    /// Multiplies the damage of every shot, melee swing and grenade blast
+    pub damage_multiplier: u32,
+    /// Seconds between the end of one round and the start of the next
+    pub round_over_delay: f32,
+    /// Seconds a player-placed barrier lasts before crumbling, 0 keeps them
+    /// until they're shot or the round ends
+    pub barrier_lifetime: f32,
+    /// Let players place barriers and slow tiles. Without building, the map's
+    /// own walls are the only cover.
+    pub building_enabled: bool,
+    /// Shots a player-placed barrier takes from its own placer before breaking
+    pub own_barrier_hits: u32,
+    /// Shots a player-placed barrier takes from the placer's opponents before breaking
+    pub enemy_barrier_hits: u32,
+    /// Fixed match seed. When set it replaces the seed derived from peer IDs,
+    /// so maps and spawns are the same every run. All peers must pass the
+    /// same value or they will desync.
+    pub seed: Option<u64>,
+    /// Hitbox radius of players, used against projectiles and barriers
+    pub player_radius: f32,
+    /// Hitbox radius of projectiles, used against players and barriers
+    pub projectile_radius: f32,
+    /// Distance from a player's center to their gun, in world units
+    pub gun_offset: f32,
+    /// Length the gun sprite is scaled to, in world units, whatever the size of `gun.png`
+    pub gun_length: f32,
+    pub ai_difficulty: AiDifficulty,
+    /// How hard the camera shakes on nearby deaths, 0 turns shaking off
+    pub screen_shake_strength: f32,
+    /// Seconds a screen shake takes to settle
+    pub screen_shake_duration: f32,
+    /// Seconds the camera takes to catch up with the player it follows, 0 to
+    /// keep it locked on
+    pub camera_lag: f32,
+    /// Turn off screen shake and flickering and cut down particles, for
+    /// players who find motion effects uncomfortable
+    pub reduced_motion: bool,
+    /// Downward acceleration of lobbed grenades, in world units per second
+    /// squared. Lower values throw them higher and further.
+    pub grenade_gravity: f32,
+    /// Seconds a shrinking safe zone takes to close in from the edges of the
+    /// map, hurting players caught outside it. `None` leaves the whole map safe.
+    pub safe_zone_seconds: Option<f32>,
+    /// Rollback frames simulated per second. All peers must agree on it.
+    pub tick_rate: usize,
+    /// Instead of starting the game, run this many frames of a bot match
+    /// headless twice and check both runs end in the same state
+    pub check_determinism: Option<u32>,
+    /// File the match's inputs are saved to as a replay once it ends
+    pub record_replay: Option<PathBuf>,
+    /// Replay file to watch instead of playing
+    pub replay: Option<PathBuf>,
+    /// Maps played in turn, moving to the next each round and starting over
+    /// after the last. All peers must choose the same maps.
+    pub map_rotation: Vec<MapChoice>,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            room: String::from(DEFAULT_ROOM),
+            fog_of_war: false,
+            wall_slam: false,
+            wrap_edges: false,
+            still_dummies: false,
+            base_player_speed: 10.0,
+            game_mode: GameMode::ScoreLimit,
+            winning_score: 5,
+            max_rounds: 5,
+            lives: 3,
+            barrier_target: 10,
+            player_colors: DEFAULT_PLAYER_COLORS.to_vec(),
+            aim_preview: AimPreview::Off,
+            fire_mode: FireMode::SemiAuto,
+            starting_weapon: Weapon::Pistol,
+            damage_multiplier: 1,
+            round_over_delay: 1.0,
+            barrier_lifetime: 0.0,
+            building_enabled: true,
+            own_barrier_hits: 1,
+            enemy_barrier_hits: 1,
+            seed: None,
+            player_radius: PLAYER_RADIUS,
+            projectile_radius: PROJECTILE_RADIUS,
+            gun_offset: 0.5,
+            gun_length: 0.64,
+            ai_difficulty: AiDifficulty::Normal,
+            screen_shake_strength: 1.0,
+            screen_shake_duration: 0.3,
+            camera_lag: 0.0,
+            reduced_motion: false,
+            grenade_gravity: 20.0,
+            safe_zone_seconds: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            check_determinism: None,
+            record_replay: None,
+            replay: None,
+            map_rotation: vec![MapChoice::Random],
+        }
+    }
+}
+
+impl GameSettings {
+    /// Simulated seconds that pass each rollback frame
+    pub fn frame_delta(&self) -> f32 {
+        1.0 / self.tick_rate as f32
+    }
+
+    /// Builds settings from the settings file's contents and the command line
+    /// flags, using defaults for anything given in neither
+    pub fn load(config: Option<&str>, args: &[String]) -> Result<Self, String> {
+        let file: SettingOverrides = match config {
+            Some(contents) => toml::from_str(contents).map_err(|error| format!("Bad settings file: {error}"))?,
+            None => SettingOverrides::default(),
+        };
+        file.overridden_by(SettingOverrides::from_args(args)?).apply()
+    }
+}
+
+/// Settings given in the settings file or on the command line, each `None`
+/// when left out
+///
+/// Keys are the command line flags without the leading dashes. The file and
+/// the command line are each read into one of these and merged field by
+/// field, with the command line winning, before anything is checked, so both
+/// go through the same range checks.
+///
+/// The player count and map size can't be set here. `NUM_PLAYERS` fixes the
+/// size of the GGRS session and of every per-player array, and `WORLD_SIZE`
+/// is shared with the preset maps and capped at 64 cells by the 6-bit cursor
+/// fields of the input word, so both are fixed when the game is built.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct SettingOverrides {
+    room: Option<String>,
+    fog_of_war: Option<bool>,
+    wall_slam: Option<bool>,
+    wrap_edges: Option<bool>,
+    still_dummies: Option<bool>,
+    reduced_motion: Option<bool>,
+    player_speed: Option<f32>,
+    mode: Option<String>,
+    winning_score: Option<u32>,
+    rounds: Option<u32>,
+    lives: Option<u32>,
+    barrier_target: Option<u32>,
+    colors: Option<Vec<String>>,
+    aim_preview: Option<String>,
+    fire_mode: Option<String>,
+    weapon: Option<String>,
+    damage: Option<u32>,
+    round_over_delay: Option<f32>,
+    barrier_lifetime: Option<f32>,
+    building: Option<bool>,
+    own_barrier_hits: Option<u32>,
+    enemy_barrier_hits: Option<u32>,
+    seed: Option<u64>,
+    player_radius: Option<f32>,
+    projectile_radius: Option<f32>,
+    gun_offset: Option<f32>,
+    gun_length: Option<f32>,
+    ai_difficulty: Option<String>,
+    screen_shake: Option<f32>,
+    screen_shake_duration: Option<f32>,
+    camera_lag: Option<f32>,
+    grenade_gravity: Option<f32>,
+    safe_zone: Option<f32>,
+    tick_rate: Option<usize>,
+    map: Option<Vec<String>>,
+    // Only affect this run, so they can't go in the file
+    #[serde(skip)]
+    check_determinism: Option<u32>,
+    #[serde(skip)]
+    record_replay: Option<PathBuf>,
+    #[serde(skip)]
+    replay: Option<PathBuf>,
+}
+
+impl SettingOverrides {
+    /// Reads the settings given as command line flags
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut overrides = Self::default();
+        let mut args = args.iter().cloned();
+
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{flag} needs a value"))
+            };
+            // Switches take an optional `=true` or `=false`, so the command
+            // line can turn off a switch the settings file turned on
+            let switch = || parse_switch(&flag, inline_value.as_deref());
+
+            match flag.as_str() {
+                "--room" => overrides.room = Some(value()?),
+                "--fog-of-war" => overrides.fog_of_war = Some(switch()?),
+                "--wall-slam" => overrides.wall_slam = Some(switch()?),
+                "--wrap-edges" => overrides.wrap_edges = Some(switch()?),
+                "--still-dummies" => overrides.still_dummies = Some(switch()?),
+                "--reduced-motion" => overrides.reduced_motion = Some(switch()?),
+                "--player-speed" => overrides.player_speed = Some(parse_number(&flag, &value()?)?),
+                "--mode" => overrides.mode = Some(value()?),
+                "--winning-score" => overrides.winning_score = Some(parse_number(&flag, &value()?)?),
+                "--rounds" => overrides.rounds = Some(parse_number(&flag, &value()?)?),
+                "--lives" => overrides.lives = Some(parse_number(&flag, &value()?)?),
+                "--barrier-target" => overrides.barrier_target = Some(parse_number(&flag, &value()?)?),
+                "--colors" => overrides.colors = Some(split_list(&value()?)),
+                "--aim-preview" => overrides.aim_preview = Some(value()?),
+                "--fire-mode" => overrides.fire_mode = Some(value()?),
+                "--weapon" => overrides.weapon = Some(value()?),
+                "--damage" => overrides.damage = Some(parse_number(&flag, &value()?)?),
+                "--round-over-delay" => overrides.round_over_delay = Some(parse_number(&flag, &value()?)?),
+                "--barrier-lifetime" => overrides.barrier_lifetime = Some(parse_number(&flag, &value()?)?),
+                "--building" => overrides.building = Some(switch()?),
+                "--no-building" => overrides.building = Some(!switch()?),
+                "--own-barrier-hits" => overrides.own_barrier_hits = Some(parse_number(&flag, &value()?)?),
+                "--enemy-barrier-hits" => overrides.enemy_barrier_hits = Some(parse_number(&flag, &value()?)?),
+                "--seed" => {
+                    let seed = value()?;
+                    overrides.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("{flag} expects a whole number, got {seed}"))?,
+                    )
+                }
+                "--player-radius" => overrides.player_radius = Some(parse_number(&flag, &value()?)?),
+                "--projectile-radius" => overrides.projectile_radius = Some(parse_number(&flag, &value()?)?),
+                "--gun-offset" => overrides.gun_offset = Some(parse_number(&flag, &value()?)?),
+                "--gun-length" => overrides.gun_length = Some(parse_number(&flag, &value()?)?),
+                "--ai-difficulty" => overrides.ai_difficulty = Some(value()?),
+                "--screen-shake" => overrides.screen_shake = Some(parse_number(&flag, &value()?)?),
+                "--screen-shake-duration" => {
+                    overrides.screen_shake_duration = Some(parse_number(&flag, &value()?)?)
+                }
+                "--camera-lag" => overrides.camera_lag = Some(parse_number(&flag, &value()?)?),
+                "--grenade-gravity" => overrides.grenade_gravity = Some(parse_number(&flag, &value()?)?),
+                "--safe-zone" => overrides.safe_zone = Some(parse_number(&flag, &value()?)?),
+                "--tick-rate" => overrides.tick_rate = Some(parse_number(&flag, &value()?)?),
+                "--record-replay" => overrides.record_replay = Some(PathBuf::from(value()?)),
+                "--replay" => overrides.replay = Some(PathBuf::from(value()?)),
+                "--check-determinism" => overrides.check_determinism = Some(parse_number(&flag, &value()?)?),
+                "--map" => overrides.map = Some(split_list(&value()?)),
+                // Already read by `read_config_file`
+                "--config" => {
+                    value()?;
+                }
+                _ => return Err(format!("Unknown flag {flag}")),
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// These settings, with those given in `overrides` taking their place
+    fn overridden_by(self, overrides: Self) -> Self {
+        Self {
+            room: overrides.room.or(self.room),
+            fog_of_war: overrides.fog_of_war.or(self.fog_of_war),
+            wall_slam: overrides.wall_slam.or(self.wall_slam),
+            wrap_edges: overrides.wrap_edges.or(self.wrap_edges),
+            still_dummies: overrides.still_dummies.or(self.still_dummies),
+            reduced_motion: overrides.reduced_motion.or(self.reduced_motion),
+            player_speed: overrides.player_speed.or(self.player_speed),
+            mode: overrides.mode.or(self.mode),
+            winning_score: overrides.winning_score.or(self.winning_score),
+            rounds: overrides.rounds.or(self.rounds),
+            lives: overrides.lives.or(self.lives),
+            barrier_target: overrides.barrier_target.or(self.barrier_target),
+            colors: overrides.colors.or(self.colors),
+            aim_preview: overrides.aim_preview.or(self.aim_preview),
+            fire_mode: overrides.fire_mode.or(self.fire_mode),
+            weapon: overrides.weapon.or(self.weapon),
+            damage: overrides.damage.or(self.damage),
+            round_over_delay: overrides.round_over_delay.or(self.round_over_delay),
+            barrier_lifetime: overrides.barrier_lifetime.or(self.barrier_lifetime),
+            building: overrides.building.or(self.building),
+            own_barrier_hits: overrides.own_barrier_hits.or(self.own_barrier_hits),
+            enemy_barrier_hits: overrides.enemy_barrier_hits.or(self.enemy_barrier_hits),
+            seed: overrides.seed.or(self.seed),
+            player_radius: overrides.player_radius.or(self.player_radius),
+            projectile_radius: overrides.projectile_radius.or(self.projectile_radius),
+            gun_offset: overrides.gun_offset.or(self.gun_offset),
+            gun_length: overrides.gun_length.or(self.gun_length),
+            ai_difficulty: overrides.ai_difficulty.or(self.ai_difficulty),
+            screen_shake: overrides.screen_shake.or(self.screen_shake),
+            screen_shake_duration: overrides.screen_shake_duration.or(self.screen_shake_duration),
+            camera_lag: overrides.camera_lag.or(self.camera_lag),
+            grenade_gravity: overrides.grenade_gravity.or(self.grenade_gravity),
+            safe_zone: overrides.safe_zone.or(self.safe_zone),
+            tick_rate: overrides.tick_rate.or(self.tick_rate),
+            map: overrides.map.or(self.map),
+            check_determinism: overrides.check_determinism.or(self.check_determinism),
+            record_replay: overrides.record_replay.or(self.record_replay),
+            replay: overrides.replay.or(self.replay),
+        }
+    }
+
+    /// Checks every given setting and applies it over the defaults
+    fn apply(self) -> Result<GameSettings, String> {
+        let mut settings = GameSettings::default();
+
+        if let Some(room) = self.room {
+            settings.room = validate_room_code(&room)?;
+        }
+        set_switch(&mut settings.fog_of_war, self.fog_of_war);
+        set_switch(&mut settings.wall_slam, self.wall_slam);
+        set_switch(&mut settings.wrap_edges, self.wrap_edges);
+        set_switch(&mut settings.still_dummies, self.still_dummies);
+        set_switch(&mut settings.reduced_motion, self.reduced_motion);
+        set_in_range(&mut settings.base_player_speed, "--player-speed", self.player_speed, 1.0..=50.0)?;
+        if let Some(mode) = self.mode {
+            settings.game_mode = parse_game_mode(&mode)?;
+        }
+        set_in_range(&mut settings.winning_score, "--winning-score", self.winning_score, 1..=99)?;
+        set_in_range(&mut settings.max_rounds, "--rounds", self.rounds, 1..=99)?;
+        set_in_range(&mut settings.lives, "--lives", self.lives, 1..=99)?;
+        set_in_range(&mut settings.barrier_target, "--barrier-target", self.barrier_target, 1..=99)?;
+        if let Some(colors) = self.colors {
+            settings.player_colors = parse_colors(&colors)?;
+        }
+        if let Some(aim_preview) = self.aim_preview {
+            settings.aim_preview = parse_aim_preview(&aim_preview)?;
+        }
+        if let Some(fire_mode) = self.fire_mode {
+            settings.fire_mode = parse_fire_mode(&fire_mode)?;
+        }
+        if let Some(weapon) = self.weapon {
+            settings.starting_weapon = parse_weapon(&weapon)?;
+        }
+        set_in_range(&mut settings.damage_multiplier, "--damage", self.damage, 1..=5)?;
+        set_in_range(&mut settings.round_over_delay, "--round-over-delay", self.round_over_delay, 0.5..=10.0)?;
+        set_in_range(&mut settings.barrier_lifetime, "--barrier-lifetime", self.barrier_lifetime, 0.0..=300.0)?;
+        set_switch(&mut settings.building_enabled, self.building);
+        set_in_range(&mut settings.own_barrier_hits, "--own-barrier-hits", self.own_barrier_hits, 1..=20)?;
+        set_in_range(&mut settings.enemy_barrier_hits, "--enemy-barrier-hits", self.enemy_barrier_hits, 1..=20)?;
+        settings.seed = self.seed;
+        set_in_range(&mut settings.player_radius, "--player-radius", self.player_radius, 0.1..=2.0)?;
+        set_in_range(&mut settings.projectile_radius, "--projectile-radius", self.projectile_radius, 0.01..=1.0)?;
+        set_in_range(&mut settings.gun_offset, "--gun-offset", self.gun_offset, 0.0..=2.0)?;
+        set_in_range(&mut settings.gun_length, "--gun-length", self.gun_length, 0.1..=3.0)?;
+        if let Some(ai_difficulty) = self.ai_difficulty {
+            settings.ai_difficulty = parse_ai_difficulty(&ai_difficulty)?;
+        }
+        set_in_range(&mut settings.screen_shake_strength, "--screen-shake", self.screen_shake, 0.0..=2.0)?;
+        set_in_range(
+            &mut settings.screen_shake_duration,
+            "--screen-shake-duration",
+            self.screen_shake_duration,
+            0.05..=2.0,
+        )?;
+        set_in_range(&mut settings.camera_lag, "--camera-lag", self.camera_lag, 0.0..=1.0)?;
+        set_in_range(&mut settings.grenade_gravity, "--grenade-gravity", self.grenade_gravity, 5.0..=100.0)?;
+        if let Some(seconds) = self.safe_zone {
+            settings.safe_zone_seconds = Some(check_in_range("--safe-zone", seconds, 10.0..=600.0)?);
+        }
+        set_in_range(&mut settings.tick_rate, "--tick-rate", self.tick_rate, 20..=120)?;
+        if let Some(frames) = self.check_determinism {
+            settings.check_determinism = Some(check_in_range("--check-determinism", frames, 1..=100_000)?);
+        }
+        settings.record_replay = self.record_replay;
+        settings.replay = self.replay;
+        if let Some(maps) = self.map {
+            settings.map_rotation = parse_map_rotation(&maps)?;
+        }
+
+        Ok(settings)
+    }
+}
+
+/// The settings file read at startup
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Reads the settings file named by `--config`, or `settings.toml` if there is one
+///
+/// A missing `settings.toml` is fine, but a `--config` file that can't be
+/// read is an error.
+pub fn read_config_file(args: &[String]) -> Result<Option<ConfigFile>, String> {
+    let explicit_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .map(|index| args.get(index + 1).cloned().ok_or("--config needs a value"))
+        .or_else(|| args.iter().find_map(|arg| arg.strip_prefix("--config=").map(|path| Ok(path.to_string()))))
+        .transpose()?;
+
+    let path = PathBuf::from(explicit_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH));
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(ConfigFile { path, contents })),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound && explicit_path.is_none() => Ok(None),
+        Err(error) => Err(format!("Could not read {}: {error}", path.display())),
+    }
+}
+
+/// Where a setting that isn't left at its default was given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingSource {
+    ConfigFile,
+    CommandLine,
+}
+
+/// Every setting given in the settings file or on the command line, logged at startup
+#[derive(Resource, Default)]
+pub struct SettingSources {
+    /// Settings file that was read, if there was one
+    pub config_path: Option<PathBuf>,
+    /// Settings by key in the order they were given, each with where its final value came from
+    pub settings: Vec<(String, SettingSource)>,
+}
+
+impl SettingSources {
+    /// Works out where each setting came from, once `GameSettings::load` has accepted them
+    pub fn new(config: Option<&ConfigFile>, args: &[String]) -> Self {
+        let mut sources = Self::default();
+        if let Some(config) = config {
+            let table: toml::Table = config.contents.parse().unwrap_or_default();
+            sources.settings = table.keys().map(|key| (key.clone(), SettingSource::ConfigFile)).collect();
+            sources.config_path = Some(config.path.clone());
+        }
+
+        for arg in args {
+            let Some(flag) = arg.strip_prefix("--") else {
+                continue;
+            };
+            let key = flag.split_once('=').map_or(flag, |(key, _)| key);
+            // Which file to read isn't a setting of its own
+            if key == "config" {
+                continue;
+            }
+            let key = if key == "no-building" { "building" } else { key };
+            sources.settings.retain(|(given, _)| given != key);
+            sources.settings.push((key.to_string(), SettingSource::CommandLine));
+        }
+        sources
+    }
+}
+
+/// Logs where every setting that isn't left at its default came from
+pub fn log_setting_sources(sources: Res<SettingSources>) {
+    match &sources.config_path {
+        Some(path) => info!("Read settings from {}", path.display()),
+        None => info!("No {DEFAULT_CONFIG_PATH} found, using defaults and command line flags"),
+    }
+    for (key, source) in &sources.settings {
+        let source = match source {
+            SettingSource::ConfigFile => "settings file",
+            SettingSource::CommandLine => "command line",
+        };
+        info!("{key} set from the {source}");
+    }
+    info!("Every other setting uses its default");
+}
+
+/// Reads a switch given on the command line, on unless it's followed by `=false`
+fn parse_switch(flag: &str, value: Option<&str>) -> Result<bool, String> {
+    match value {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(value) => Err(format!("{flag} expects true or false, got {value}")),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{flag} expects a number, got {value}"))
+}
+
+/// Splits a comma separated list given on the command line
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|item| item.trim().to_string()).collect()
+}
+
+fn set_switch(setting: &mut bool, value: Option<bool>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
+
+/// Overwrites `setting` with `value`, if one was given, once it's checked to lie within the allowed range
+fn set_in_range<T>(setting: &mut T, flag: &str, value: Option<T>, range: RangeInclusive<T>) -> Result<(), String>
+where
+    T: PartialOrd + std::fmt::Display,
+{
+    if let Some(value) = value {
+        *setting = check_in_range(flag, value, range)?;
+    }
+    Ok(())
+}
+
+/// Checks a setting lies within the allowed range
+fn check_in_range<T>(flag: &str, value: T, range: RangeInclusive<T>) -> Result<T, String>
+where
+    T: PartialOrd + std::fmt::Display,
+{
+    if !range.contains(&value) {
+        return Err(format!(
+            "{flag} must be between {} and {}",
+            range.start(),
+            range.end()
+        ));
+    }
+
+    Ok(value)
+}
+
+fn parse_game_mode(value: &str) -> Result<GameMode, String> {
+    match value {
+        "score-limit" => Ok(GameMode::ScoreLimit),
+        "best-of" => Ok(GameMode::BestOf),
+        "elimination" => Ok(GameMode::Elimination),
+        "demolition" => Ok(GameMode::Demolition),
+        _ => Err(format!(
+            "Unknown game mode {value}, expected score-limit, best-of, elimination or demolition"
+        )),
+    }
+}
+
+fn parse_aim_preview(value: &str) -> Result<AimPreview, String> {
+    match value {
+        "off" => Ok(AimPreview::Off),
+        "always" => Ok(AimPreview::Always),
+        "shooting" => Ok(AimPreview::WhileShooting),
+        _ => Err(format!(
+            "Unknown aim preview {value}, expected off, always or shooting"
+        )),
+    }
+}
+
+fn parse_fire_mode(value: &str) -> Result<FireMode, String> {
+    match value {
+        "auto" => Ok(FireMode::Automatic),
+        "semi" => Ok(FireMode::SemiAuto),
+        _ => Err(format!("Unknown fire mode {value}, expected auto or semi")),
+    }
+}
+
+fn parse_weapon(value: &str) -> Result<Weapon, String> {
+    match value {
+        "pistol" => Ok(Weapon::Pistol),
+        "shotgun" => Ok(Weapon::Shotgun),
+        "rocket" => Ok(Weapon::Rocket),
+        "incendiary" => Ok(Weapon::Incendiary),
+        "railgun" => Ok(Weapon::Railgun),
+        "grenade" => Ok(Weapon::Grenade),
+        _ => Err(format!(
+            "Unknown weapon {value}, expected pistol, shotgun, rocket, incendiary, railgun or grenade"
+        )),
+    }
+}
+
+fn parse_ai_difficulty(value: &str) -> Result<AiDifficulty, String> {
+    match value {
+        "easy" => Ok(AiDifficulty::Easy),
+        "normal" => Ok(AiDifficulty::Normal),
+        "hard" => Ok(AiDifficulty::Hard),
+        _ => Err(format!(
+            "Unknown AI difficulty {value}, expected easy, normal or hard"
+        )),
+    }
+}
+
+/// Parses a list of maps, e.g. `crossroads,random,fortress` on the command line
+fn parse_map_rotation(maps: &[String]) -> Result<Vec<MapChoice>, String> {
+    maps.iter().map(|map| parse_map(map)).collect()
+}
+
+fn parse_map(value: &str) -> Result<MapChoice, String> {
+    if value == "random" {
+        return Ok(MapChoice::Random);
+    }
+
+    if PRESET_MAPS.contains(&value) {
+        return Ok(MapChoice::Preset(value.to_string()));
+    }
+
+    Err(format!(
+        "Unknown map {value}, expected random or one of {}",
+        PRESET_MAPS.join(", ")
+    ))
+}
+
+/// Parses a list of hex colors, e.g. `ff0000,0000ff` on the command line
+fn parse_colors(colors: &[String]) -> Result<Vec<Color>, String> {
+    colors
+        .iter()
+        .map(|hex| {
+            Srgba::hex(hex)
+                .map(Color::from)
+                .map_err(|_| format!("{hex} is not a hex color"))
+        })
+        .collect()
+}
+
+/// Checks a room code is safe to put in the matchbox URL
+fn validate_room_code(code: &str) -> Result<String, String> {
+    if code.is_empty() || code.len() > MAX_ROOM_CODE_LENGTH {
+        return Err(format!(
+            "Room code must be between 1 and {MAX_ROOM_CODE_LENGTH} characters"
+        ));
+    }
+
+    if !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(String::from("Room code may only contain letters and digits"));
+    }
+
+    Ok(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_without_a_file_or_flags() {
+        let settings = GameSettings::load(None, &[]).unwrap();
+        assert_eq!(settings.winning_score, GameSettings::default().winning_score);
+        assert!(settings.building_enabled);
+    }
+
+    #[test]
+    fn file_values_are_applied() {
+        let file = "winning-score = 3\nwall-slam = true\nweapon = \"shotgun\"\n";
+        let settings = GameSettings::load(Some(file), &[]).unwrap();
+        assert_eq!(settings.winning_score, 3);
+        assert!(settings.wall_slam);
+        assert_eq!(settings.starting_weapon, Weapon::Shotgun);
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let file = "winning-score = 3\nwall-slam = true\nbuilding = true\n";
+        let settings = GameSettings::load(
+            Some(file),
+            &args(&["--winning-score", "7", "--wall-slam=false", "--no-building"]),
+        )
+        .unwrap();
+        assert_eq!(settings.winning_score, 7);
+        assert!(!settings.wall_slam);
+        assert!(!settings.building_enabled);
+    }
+
+    #[test]
+    fn file_values_are_range_checked() {
+        let error = GameSettings::load(Some("winning-score = 0\n"), &[]).unwrap_err();
+        assert_eq!(error, "--winning-score must be between 1 and 99");
+        assert!(GameSettings::load(None, &args(&["--damage=6"])).is_err());
+        // An out of range file value is still fine when the command line replaces it
+        assert!(GameSettings::load(Some("damage = 9\n"), &args(&["--damage", "2"])).is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(GameSettings::load(Some("winning-scor = 3\n"), &[]).is_err());
+        assert!(GameSettings::load(Some("record-replay = \"match.replay\"\n"), &[]).is_err());
+        assert!(GameSettings::load(None, &args(&["--winning-scor", "3"])).is_err());
+    }
+
+    #[test]
+    fn config_flag_is_not_reported_as_a_setting() {
+        let sources = SettingSources::new(None, &args(&["--config", "other.toml", "--no-building"]));
+        assert_eq!(sources.settings, vec![("building".to_string(), SettingSource::CommandLine)]);
+    }
+}
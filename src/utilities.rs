@@ -1,10 +1,128 @@
+use std::f32::consts::TAU;
 use bevy::prelude::*;
-use bevy::reflect::List;
-use crate::GamePhase;
-use crate::network_manager::NUM_PLAYERS;
+use bevy_ggrs::PlayerInputs;
+use rand::distributions::uniform::{SampleRange, SampleUniform};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use crate::{GameConfig, GamePhase, WORLD_SIZE};
+use crate::input_handler::is_ready;
+use crate::network_manager::{RandomSeed, NUM_PLAYERS};
+use crate::settings::{GameMode, GameSettings};
 
-#[derive(Resource, Clone, Deref, DerefMut)]
-pub struct RoundTimer(Timer);
+/// Counts down a fixed number of rollback frames.
+///
+/// `Res<Time>` must not drive anything inside `GgrsSchedule`: how much real
+/// time passed differs between peers and between a frame's first simulation
+/// and its re-simulation after a rollback. Every timer in the rollback
+/// simulation counts whole frames with this type instead, so it fires on the
+/// same frame everywhere. Store it in a rollback-registered component or
+/// resource and call `tick` once per simulated frame. For the same reason,
+/// movement uses `GameSettings::frame_delta` rather than the frame time.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FrameTimer {
+    pub frames_remaining: u32,
+}
+
+impl FrameTimer {
+    pub fn from_frames(frames: u32) -> Self {
+        Self {
+            frames_remaining: frames,
+        }
+    }
+
+    /// Converts a duration to the nearest whole number of rollback frames at `tick_rate`
+    pub fn from_seconds(seconds: f32, tick_rate: usize) -> Self {
+        Self::from_frames((seconds * tick_rate as f32).round() as u32)
+    }
+
+    /// Advances by one frame, returning true only on the frame the timer runs out
+    pub fn tick(&mut self) -> bool {
+        if self.frames_remaining == 0 {
+            return false;
+        }
+
+        self.frames_remaining -= 1;
+        self.frames_remaining == 0
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frames_remaining == 0
+    }
+}
+
+/// Brings a position that has left the map back in from the opposite edge
+pub fn wrap_position(position: Vec2) -> Vec2 {
+    let size = WORLD_SIZE as f32;
+    let half_size = size / 2.;
+    Vec2::new(
+        (position.x + half_size).rem_euclid(size) - half_size,
+        (position.y + half_size).rem_euclid(size) - half_size,
+    )
+}
+
+/// The generator every random choice in the rollback simulation draws from
+///
+/// It's reseeded from the match seed at the start of every round and
+/// rolled back with the rest of the state, so each peer draws the same
+/// numbers. Systems using it must run in a fixed order relative to each
+/// other: `create_world` draws first, then `initialize_players`.
+///
+/// Player colors come from the settings rather than a draw, so nothing in
+/// the simulation needs a random color and there's no helper for one.
+#[derive(Resource, Clone)]
+pub struct GameRng(Xoshiro256PlusPlus);
+
+impl GameRng {
+    /// Generator for one round of a match
+    fn for_round(match_seed: u64, round: u32) -> Self {
+        // Spread consecutive rounds far apart in the seed space
+        let round_seed = match_seed ^ (round as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        GameRng(Xoshiro256PlusPlus::seed_from_u64(round_seed))
+    }
+
+    pub fn range<T, R>(&mut self, range: R) -> T
+    where
+        T: SampleUniform,
+        R: SampleRange<T>,
+    {
+        self.0.gen_range(range)
+    }
+
+    /// A point anywhere on the map
+    pub fn position(&mut self) -> Vec2 {
+        let half_world_size = WORLD_SIZE as f32 * 0.5;
+        Vec2::new(
+            self.range(-half_world_size..half_world_size),
+            self.range(-half_world_size..half_world_size),
+        )
+    }
+
+    /// The next number the generator would draw, without drawing it, so
+    /// generators can be compared
+    pub fn fingerprint(&self) -> u64 {
+        self.0.clone().gen()
+    }
+
+    /// A unit vector pointing anywhere
+    pub fn direction(&mut self) -> Vec2 {
+        Vec2::from_angle(self.range(0.0..TAU))
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::for_round(0, 0)
+    }
+}
+
+/// Reseeds the generator before anything is drawn for a new round
+pub fn reseed_game_rng(mut rng: ResMut<GameRng>, seed: Res<RandomSeed>, progress: Res<MatchProgress>) {
+    *rng = GameRng::for_round(**seed, progress.rounds_played);
+}
+
+/// Delay between the end of a round and the start of the next
+#[derive(Resource, Clone, Copy, Deref, DerefMut)]
+pub struct RoundTimer(FrameTimer);
 
 #[derive(Resource, Default, Clone)]
 // Tuple with capacity NUM_PLAYERS
@@ -28,20 +146,449 @@ impl PlayerScores {
     }
 }
 
+impl PlayerScores {
+    /// Returns the handle of the first player to reach `limit`
+    pub fn reached(&self, limit: u64) -> Option<usize> {
+        self.scores.iter().position(|&score| score >= limit)
+    }
+
+    /// Returns the handle of the player with the highest score, unless it's tied
+    pub fn leader(&self) -> Option<usize> {
+        let best = *self.scores.iter().max()?;
+        let mut leaders = self.scores.iter().enumerate().filter(|(_, &score)| score == best);
+        let (leader, _) = leaders.next()?;
+        leaders.next().is_none().then_some(leader)
+    }
+}
+
+/// How far through the match we are
+#[derive(Resource, Default, Clone, Copy)]
+pub struct MatchProgress {
+    /// Rounds that have finished so far
+    pub rounds_played: u32,
+    /// Whether the match ended tied and went to sudden death
+    pub sudden_death: bool,
+}
+
+/// Counts each round as it ends
+pub fn count_finished_round(mut progress: ResMut<MatchProgress>) {
+    progress.rounds_played += 1;
+}
+
+/// Where the match stands after a round
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchResult {
+    /// More rounds are needed
+    Undecided,
+    /// The match is over and this player won it
+    Won(usize),
+    /// The match is over but the top scores are tied
+    Tied,
+}
+
+/// Decides whether the match is over under the chosen game mode
+pub fn match_result(
+    settings: &GameSettings,
+    player_scores: &PlayerScores,
+    progress: &MatchProgress,
+    lives: &PlayerLives,
+) -> MatchResult {
+    let finished = match settings.game_mode {
+        GameMode::ScoreLimit => player_scores.reached(settings.winning_score as u64).is_some(),
+        GameMode::Demolition => player_scores.reached(settings.barrier_target as u64).is_some(),
+        GameMode::BestOf => progress.rounds_played >= settings.max_rounds,
+        // Decided by who has lives left rather than by score
+        GameMode::Elimination => {
+            let mut remaining = (0..NUM_PLAYERS).filter(|&player| !lives.is_eliminated(player));
+            return match (remaining.next(), remaining.next()) {
+                (Some(winner), None) => MatchResult::Won(winner),
+                (None, _) => MatchResult::Tied,
+                _ => MatchResult::Undecided,
+            };
+        }
+    };
+    if !finished {
+        return MatchResult::Undecided;
+    }
+
+    match player_scores.leader() {
+        Some(winner) => MatchResult::Won(winner),
+        None => MatchResult::Tied,
+    }
+}
+
+/// Lives left for each player in an elimination match
+#[derive(Resource, Default, Clone)]
+pub struct PlayerLives {
+    lives: Vec<u32>,
+    /// Round each player lost their last life in, if they have
+    eliminated_in_round: Vec<Option<u32>>,
+}
+
+impl PlayerLives {
+    pub fn new(lives: u32) -> Self {
+        Self {
+            lives: vec![lives; NUM_PLAYERS],
+            eliminated_in_round: vec![None; NUM_PLAYERS],
+        }
+    }
+
+    pub fn get(&self, player: usize) -> u32 {
+        self.lives[player]
+    }
+
+    /// Whether the player is out of lives and sits out every remaining round
+    pub fn is_eliminated(&self, player: usize) -> bool {
+        self.lives[player] == 0
+    }
+
+    /// Takes a life from a player who died during `round`
+    pub fn lose_life(&mut self, player: usize, round: u32) {
+        self.lives[player] = self.lives[player].saturating_sub(1);
+        if self.lives[player] == 0 {
+            self.eliminated_in_round[player] = Some(round);
+        }
+    }
+
+    /// Gives one life back to everyone knocked out in the latest round, so
+    /// the finalists who eliminated each other can settle it in sudden death
+    fn revive_finalists(&mut self) {
+        let Some(last_round) = self.eliminated_in_round.iter().flatten().copied().max() else {
+            return;
+        };
+
+        for (lives, eliminated_in_round) in self.lives.iter_mut().zip(&mut self.eliminated_in_round) {
+            if *eliminated_in_round == Some(last_round) {
+                *lives = 1;
+                *eliminated_in_round = None;
+            }
+        }
+    }
+}
+
+/// Kills each player has made since they last died, carried across rounds
+#[derive(Resource, Default, Clone)]
+pub struct KillStreaks {
+    streaks: Vec<u32>,
+    /// Highest streak each player has been rewarded for, so rewards are granted once
+    rewarded: Vec<u32>,
+}
+
+impl KillStreaks {
+    pub fn new() -> Self {
+        Self {
+            streaks: vec![0; NUM_PLAYERS],
+            rewarded: vec![0; NUM_PLAYERS],
+        }
+    }
+
+    /// Ends the victim's streak and extends the killer's, returning the
+    /// killer's new streak unless the death was self-inflicted
+    pub fn record_kill(&mut self, killer: usize, victim: usize) -> Option<u32> {
+        self.streaks[victim] = 0;
+        self.rewarded[victim] = 0;
+        if killer == victim {
+            return None;
+        }
+        self.streaks[killer] += 1;
+        Some(self.streaks[killer])
+    }
+
+    /// Streaks the player has reached but not been rewarded for yet, oldest first
+    pub fn claim_rewards(&mut self, player: usize) -> std::ops::RangeInclusive<u32> {
+        let unclaimed = self.rewarded[player] + 1..=self.streaks[player];
+        self.rewarded[player] = self.streaks[player];
+        unclaimed
+    }
+}
+
+/// Kills, deaths and barriers destroyed of every player over the whole match
+#[derive(Resource, Default, Clone)]
+pub struct PlayerStats {
+    kills: Vec<u32>,
+    deaths: Vec<u32>,
+    barriers_destroyed: Vec<u32>,
+}
+
+impl PlayerStats {
+    pub fn new() -> Self {
+        Self {
+            kills: vec![0; NUM_PLAYERS],
+            deaths: vec![0; NUM_PLAYERS],
+            barriers_destroyed: vec![0; NUM_PLAYERS],
+        }
+    }
+
+    pub fn kills(&self, player: usize) -> u32 {
+        self.kills[player]
+    }
+
+    pub fn deaths(&self, player: usize) -> u32 {
+        self.deaths[player]
+    }
+
+    pub fn barriers_destroyed(&self, player: usize) -> u32 {
+        self.barriers_destroyed[player]
+    }
+
+    /// Records a death, crediting the killer unless it was self-inflicted
+    pub fn record_kill(&mut self, killer: usize, victim: usize) {
+        if killer != victim {
+            self.kills[killer] += 1;
+        }
+        self.deaths[victim] += 1;
+    }
+
+    pub fn record_barrier_destroyed(&mut self, player: usize) {
+        self.barriers_destroyed[player] += 1;
+    }
+}
+
 impl Default for RoundTimer {
     fn default() -> Self {
-        RoundTimer(Timer::from_seconds(1.0, TimerMode::Repeating))
+        let settings = GameSettings::default();
+        RoundTimer(FrameTimer::from_seconds(settings.round_over_delay, settings.tick_rate))
     }
 }
+
+/// Restarts the delay each time a round ends
+pub fn reset_round_timer(mut timer: ResMut<RoundTimer>, settings: Res<GameSettings>) {
+    *timer = RoundTimer(FrameTimer::from_seconds(settings.round_over_delay, settings.tick_rate));
+}
+
 pub fn round_over_timer(
     mut timer: ResMut<RoundTimer>,
     mut state: ResMut<NextState<GamePhase>>,
-    time: Res<Time>,
+    player_scores: Res<PlayerScores>,
+    progress: Res<MatchProgress>,
+    mut lives: ResMut<PlayerLives>,
+    settings: Res<GameSettings>,
 ) {
-    println!("round_end_timeout");
-    timer.tick(time.delta());
+    if timer.tick() {
+        state.set(match match_result(&settings, &player_scores, &progress, &lives) {
+            MatchResult::Won(_) => GamePhase::MatchOver,
+            MatchResult::Tied => {
+                lives.revive_finalists();
+                GamePhase::SuddenDeath
+            }
+            MatchResult::Undecided => GamePhase::ActiveRound,
+        });
+    }
+}
+
+/// Which players have readied up during warmup
+#[derive(Resource, Default, Clone)]
+pub struct ReadyPlayers {
+    ready: Vec<bool>,
+}
+
+impl ReadyPlayers {
+    pub fn new() -> Self {
+        Self {
+            ready: vec![false; NUM_PLAYERS],
+        }
+    }
 
-    if timer.just_finished() {
+    pub fn is_ready(&self, player: usize) -> bool {
+        self.ready[player]
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.ready.iter().filter(|&&ready| ready).count()
+    }
+}
+
+/// Forgets who was ready when a new warmup begins
+pub fn reset_ready_players(mut ready_players: ResMut<ReadyPlayers>) {
+    *ready_players = ReadyPlayers::new();
+}
+
+/// Records players readying up and starts the first round once all of them have
+pub fn warmup_ready_check(
+    inputs: Res<PlayerInputs<GameConfig>>,
+    mut ready_players: ResMut<ReadyPlayers>,
+    mut state: ResMut<NextState<GamePhase>>,
+) {
+    for (player, ready) in ready_players.ready.iter_mut().enumerate() {
+        let (input, _) = inputs[player];
+        *ready |= is_ready(input);
+    }
+
+    if ready_players.ready_count() == NUM_PLAYERS {
         state.set(GamePhase::ActiveRound);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames after starting `timer` on which `tick` reports it running out
+    fn firing_frames(mut timer: FrameTimer, frames: u32) -> Vec<u32> {
+        (1..=frames).filter(|_| timer.tick()).collect()
+    }
+
+    #[test]
+    fn fires_once_on_the_last_frame() {
+        assert_eq!(firing_frames(FrameTimer::from_frames(5), 20), vec![5]);
+        assert_eq!(firing_frames(FrameTimer::from_frames(0), 20), Vec::<u32>::new());
+        assert!(FrameTimer::from_frames(0).finished());
+    }
+
+    #[test]
+    fn rounds_seconds_to_whole_frames() {
+        assert_eq!(FrameTimer::from_seconds(0.5, 60).frames_remaining, 30);
+        assert_eq!(FrameTimer::from_seconds(0.25, 30).frames_remaining, 8);
+        assert_eq!(FrameTimer::from_seconds(1.0, 20).frames_remaining, 20);
+    }
+
+    #[test]
+    fn fires_on_the_same_frame_after_a_rollback() {
+        // Snapshot the timer at the end of every frame, as bevy_ggrs does for rollback state
+        let mut timer = FrameTimer::from_frames(10);
+        let mut snapshots = vec![timer];
+        let mut fired_on = None;
+        for frame in 1..=8 {
+            if timer.tick() {
+                fired_on = Some(frame);
+            }
+            snapshots.push(timer);
+        }
+        assert_eq!(fired_on, None);
+
+        // Rewind to the end of frame 3 and simulate the frames again
+        let mut frame = 3;
+        timer = snapshots[frame];
+        while fired_on.is_none() {
+            frame += 1;
+            if timer.tick() {
+                fired_on = Some(frame);
+            }
+        }
+        assert_eq!(fired_on, Some(10));
+        assert!(timer.finished());
+    }
+
+    #[test]
+    fn rewinding_past_the_firing_frame_fires_it_again() {
+        let mut timer = FrameTimer::from_frames(4);
+        let mut snapshots = vec![timer];
+        for _ in 1..=6 {
+            timer.tick();
+            snapshots.push(timer);
+        }
+        assert!(timer.finished());
+
+        // Frame 4 is simulated again from the end of frame 2, so it fires again there
+        assert_eq!(firing_frames(snapshots[2], 4), vec![2]);
+        // From after the firing frame there's nothing left to fire
+        assert_eq!(firing_frames(snapshots[5], 4), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn rounds_draw_the_same_numbers_on_every_peer() {
+        let mut first = GameRng::for_round(1905, 3);
+        let mut second = GameRng::for_round(1905, 3);
+        let mut next_round = GameRng::for_round(1905, 4);
+        let draws: Vec<Vec2> = (0..10).map(|_| first.position()).collect();
+        assert_eq!(draws, (0..10).map(|_| second.position()).collect::<Vec<_>>());
+        assert_ne!(draws, (0..10).map(|_| next_round.position()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn directions_are_unit_vectors() {
+        let mut rng = GameRng::default();
+        for _ in 0..1000 {
+            assert!((rng.direction().length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn kill_streaks_grow_until_the_killer_dies() {
+        let mut streaks = KillStreaks::new();
+        assert_eq!(streaks.record_kill(0, 1), Some(1));
+        assert_eq!(streaks.record_kill(0, 1), Some(2));
+        // Self-inflicted deaths end the streak without extending anyone's
+        assert_eq!(streaks.record_kill(1, 1), None);
+        assert_eq!(streaks.record_kill(1, 0), Some(1));
+        assert_eq!(streaks.record_kill(0, 1), Some(1));
+    }
+
+    #[test]
+    fn streak_rewards_are_claimed_once() {
+        let mut streaks = KillStreaks::new();
+        streaks.record_kill(0, 1);
+        streaks.record_kill(0, 1);
+        assert_eq!(streaks.claim_rewards(0), 1..=2);
+        assert!(streaks.claim_rewards(0).is_empty());
+
+        streaks.record_kill(0, 1);
+        assert_eq!(streaks.claim_rewards(0), 3..=3);
+    }
+
+    fn elimination_result(lives: &PlayerLives) -> MatchResult {
+        let settings = GameSettings {
+            game_mode: GameMode::Elimination,
+            ..default()
+        };
+        match_result(&settings, &PlayerScores::new(), &MatchProgress::default(), lives)
+    }
+
+    #[test]
+    fn elimination_is_won_by_the_last_player_with_lives() {
+        let mut lives = PlayerLives::new(2);
+        lives.lose_life(0, 0);
+        assert_eq!(lives.get(0), 1);
+        assert_eq!(elimination_result(&lives), MatchResult::Undecided);
+
+        lives.lose_life(0, 1);
+        assert!(lives.is_eliminated(0));
+        assert_eq!(elimination_result(&lives), MatchResult::Won(1));
+    }
+
+    #[test]
+    fn finalists_knocked_out_together_are_revived_for_sudden_death() {
+        let mut lives = PlayerLives::new(1);
+        lives.lose_life(0, 3);
+        lives.lose_life(1, 3);
+        assert_eq!(elimination_result(&lives), MatchResult::Tied);
+
+        lives.revive_finalists();
+        assert_eq!((lives.get(0), lives.get(1)), (1, 1));
+        assert_eq!(elimination_result(&lives), MatchResult::Undecided);
+    }
+
+    #[test]
+    fn only_the_latest_round_is_revived() {
+        let mut lives = PlayerLives::new(1);
+        lives.lose_life(0, 1);
+        lives.lose_life(1, 2);
+        lives.revive_finalists();
+        assert!(lives.is_eliminated(0));
+        assert_eq!(lives.get(1), 1);
+    }
+
+    #[test]
+    fn best_of_matches_end_after_the_last_round_unless_tied() {
+        let settings = GameSettings {
+            game_mode: GameMode::BestOf,
+            max_rounds: 3,
+            ..default()
+        };
+        let lives = PlayerLives::new(settings.lives);
+        let mut scores = PlayerScores::new();
+        scores.set(0, 2);
+        let mut progress = MatchProgress {
+            rounds_played: 2,
+            ..default()
+        };
+        assert_eq!(match_result(&settings, &scores, &progress, &lives), MatchResult::Undecided);
+
+        progress.rounds_played = 3;
+        assert_eq!(match_result(&settings, &scores, &progress, &lives), MatchResult::Won(0));
+
+        // Level scores after the last round go to sudden death
+        scores.set(1, 2);
+        assert_eq!(match_result(&settings, &scores, &progress, &lives), MatchResult::Tied);
+    }
+}